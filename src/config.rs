@@ -2,95 +2,349 @@ mod anchor;
 mod compat;
 mod entry;
 mod font;
+mod layer;
+mod palette;
+mod validate;
 
 use std::env;
+use std::ffi::OsStr;
 use std::fs::read_to_string;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use anyhow::{Context, Result, bail};
-use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::seat::keyboard::Keysym;
+use smithay_client_toolkit::shell::wlr_layer::Anchor;
 
 pub use self::anchor::ConfigAnchor;
 pub use self::entry::Entry;
 pub use self::font::Font;
-use crate::color::Color;
+pub use self::layer::ConfigLayer;
+use crate::color::{self, Color};
+use crate::key::{Key, ModifierState, SingleKey};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(deny_unknown_fields, default)]
 pub struct Config {
+    /// A pywal `colors.json` or base16 scheme whose colors can be used by name in color fields.
+    pub palette: Option<PathBuf>,
     pub background: Color,
     pub color: Color,
+    /// Color of the keys, `color` if unset.
+    pub key_color: Option<Color>,
+    /// How the modifiers of keys, like `Ctrl+` in `Ctrl+x`, are set apart from the rest.
+    pub modifier_style: ModifierStyle,
     pub border: Color,
+    pub highlight: Color,
+    pub row_background_even: Option<Color>,
+    pub row_background_odd: Option<Color>,
+    /// Opacity of the whole menu, between 0 and 1.
+    pub opacity: f64,
+    /// Duration of the fade-in when the menu appears, in milliseconds.
+    pub fade_in_ms: Option<u64>,
 
+    pub output: Option<String>,
+    pub layer: ConfigLayer,
     pub anchor: ConfigAnchor,
     pub margin_top: i32,
     pub margin_right: i32,
     pub margin_bottom: i32,
     pub margin_left: i32,
+    /// Distance from the anchored edges, added to the margins of those edges.
+    pub offset_x: i32,
+    pub offset_y: i32,
+    /// Space reserved at the anchored edge, so that windows do not overlap the menu.
+    pub exclusive_zone: Option<i32>,
 
     pub font: Font,
+    pub markup: bool,
+    /// Mirror the menus whose descriptions are in a right-to-left script.
+    pub auto_rtl: bool,
+    pub icon_size: f64,
     pub separator: String,
+    /// Color of `separator`, the color of the entry if unset.
+    pub separator_color: Option<Color>,
     pub border_width: f64,
+    pub border_widths: BorderWidths,
     pub corner_r: f64,
+    pub corner_radii: CornerRadii,
+    pub shadow: Option<Shadow>,
     pub padding: Option<f64>,
     pub rows_per_column: Option<usize>,
+    pub columns: Columns,
+    pub layout: MenuLayout,
+    /// Number of tiles per row with `layout: grid`.
+    pub grid_columns: usize,
+    pub max_height: Option<u32>,
+    /// Descriptions are ellipsized so that the menu is at most this wide.
+    pub max_width: Option<u32>,
+    /// Entries shown at most in each menu, the others are left out with a count of them instead.
+    pub max_entries: Option<usize>,
+    /// The menu is at least this wide, with its contents centered.
+    pub min_width: Option<u32>,
+    /// The menu is at least this high, with its contents placed by `vertical_align`.
+    pub min_height: Option<u32>,
+    /// Where the contents go in a menu made higher than them by `min_height` or `height_fraction`.
+    pub vertical_align: VerticalAlign,
+    /// Make the menu this fraction of the output width, whatever its contents.
+    pub width_fraction: Option<f64>,
+    /// Make the menu this fraction of the output height, whatever its contents.
+    pub height_fraction: Option<f64>,
     pub column_padding: Option<f64>,
+    /// Space between consecutive rows.
+    pub row_spacing: f64,
+    /// Space between the keys and the separator, in addition to the separator text.
+    pub key_desc_gap: f64,
+    /// Alignment of the descriptions in the space left of them in their column.
+    pub text_align: TextAlign,
+    pub show_breadcrumb: bool,
+    /// Hint shown dimmed below the entries. `auto` lists the keys that work in every menu.
+    pub footer: Option<String>,
 
     pub inhibit_compositor_keyboard_shortcuts: bool,
+    /// Ignore a press of the same key with the same modifiers within this many milliseconds of
+    /// the last one.
+    pub debounce_ms: Option<u64>,
+    /// Show a submenu only while its key is held for at least this many milliseconds. Shorter
+    /// presses open it as usual.
+    pub preview_ms: Option<u64>,
     pub auto_kbd_layout: bool,
+    pub close_on_focus_loss: bool,
+
+    pub sibling_next_key: Option<Key>,
+    pub sibling_prev_key: Option<Key>,
+    pub sibling_wrap: bool,
+    pub search_key: Option<Key>,
+    /// Keys that close the menu, unless an entry of the current menu uses them.
+    pub quit_keys: Vec<SingleKey>,
+    /// Match the characters of the search query in order instead of as a whole.
+    pub search_fuzzy: bool,
+
+    /// Keep the menu open after running commands which do not set `keep_open`.
+    pub keep_open_default: bool,
+
+    pub expand_env: bool,
+    /// Give entries without a key the first of `1`-`9`, `0`, `a`-`z` not used in their menu.
+    pub auto_keys: bool,
+    pub shell: Vec<String>,
+    pub cwd: Option<PathBuf>,
 
     pub menu: Vec<Entry>,
+
+    /// The file this config was loaded from.
+    #[serde(skip)]
+    pub path: PathBuf,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            palette: Option::default(),
             background: Color::from_rgba_hex(0x282828ff),
             color: Color::from_rgba_hex(0xfbf1c7ff),
+            key_color: Option::default(),
+            modifier_style: ModifierStyle::default(),
             border: Color::from_rgba_hex(0x8ec07cff),
+            highlight: Color::from_rgba_hex(0x504945ff),
+            row_background_even: Option::default(),
+            row_background_odd: Option::default(),
+            opacity: 1.0,
+            fade_in_ms: Option::default(),
+            output: Option::default(),
+            layer: ConfigLayer::default(),
             anchor: ConfigAnchor::default(),
             margin_top: i32::default(),
             margin_right: i32::default(),
             margin_bottom: i32::default(),
             margin_left: i32::default(),
+            offset_x: i32::default(),
+            offset_y: i32::default(),
+            exclusive_zone: Option::default(),
             font: Font::new("monospace 10"),
+            markup: bool::default(),
+            auto_rtl: bool::default(),
+            icon_size: 16.0,
             separator: " ➜ ".into(),
+            separator_color: Option::default(),
             border_width: 4.0,
+            border_widths: BorderWidths::default(),
             corner_r: 20.0,
+            corner_radii: CornerRadii::default(),
+            shadow: Option::default(),
             padding: Option::default(),
             rows_per_column: Option::default(),
+            columns: Columns::default(),
+            layout: MenuLayout::default(),
+            grid_columns: 4,
+            max_height: Option::default(),
+            max_width: Option::default(),
+            max_entries: Option::default(),
+            min_width: Option::default(),
+            min_height: Option::default(),
+            vertical_align: VerticalAlign::default(),
+            width_fraction: Option::default(),
+            height_fraction: Option::default(),
             column_padding: Option::default(),
+            row_spacing: 0.0,
+            key_desc_gap: 0.0,
+            text_align: TextAlign::default(),
+            show_breadcrumb: bool::default(),
+            footer: Option::default(),
             inhibit_compositor_keyboard_shortcuts: bool::default(),
+            debounce_ms: Option::default(),
+            preview_ms: Option::default(),
             auto_kbd_layout: bool::default(),
+            close_on_focus_loss: bool::default(),
+            sibling_next_key: Option::default(),
+            sibling_prev_key: Option::default(),
+            sibling_wrap: bool::default(),
+            search_key: Option::default(),
+            quit_keys: default_quit_keys(),
+            search_fuzzy: bool::default(),
+            keep_open_default: bool::default(),
+            expand_env: bool::default(),
+            auto_keys: bool::default(),
+            shell: default_shell(),
+            cwd: Option::default(),
             menu: Vec::default(),
+            path: PathBuf::default(),
         }
     }
 }
 
 impl Config {
-    pub fn new(name: &str) -> Result<Self> {
-        let mut config_path = config_dir().context("Cound not find config directory")?;
-        config_path.push("wlr-which-key");
-        config_path.push(name);
-        config_path.set_extension("yaml");
+    pub fn new(name: impl AsRef<Path>) -> Result<Self> {
+        let config_path = resolve_path(name)?;
+        Self::read(&config_path)?.prepare(config_path)
+    }
+
+    /// Read a YAML config from stdin. It has no `path`, and includes are looked up as usual.
+    pub fn from_stdin() -> Result<Self> {
+        let mut config_str = String::new();
+        io::stdin()
+            .read_to_string(&mut config_str)
+            .context("Failed to read configuration from stdin")?;
+        Self::from_yaml_str(&config_str)
+    }
+
+    /// Parse a YAML config which is not read from a file, like `from_stdin`.
+    pub fn from_yaml_str(config_str: &str) -> Result<Self> {
+        Self::parse(config_str, None)?.prepare(PathBuf::new())
+    }
+
+    /// Check and complete a freshly parsed config read from `config_path`.
+    fn prepare(mut self, config_path: PathBuf) -> Result<Self> {
+        if self.shell.is_empty() {
+            bail!("'shell' must contain at least the program to run");
+        }
+        if self.row_spacing < 0.0 {
+            bail!("'row_spacing' cannot be negative");
+        }
+        if self.key_desc_gap < 0.0 {
+            bail!("'key_desc_gap' cannot be negative");
+        }
+        if let (Some(min), Some(max)) = (self.min_width, self.max_width)
+            && min > max
+        {
+            bail!("'min_width' cannot be greater than 'max_width'");
+        }
+        if let (Some(min), Some(max)) = (self.min_height, self.max_height)
+            && min > max
+        {
+            bail!("'min_height' cannot be greater than 'max_height'");
+        }
+        if self.max_entries == Some(0) {
+            bail!("'max_entries' must be at least 1");
+        }
+        if self.grid_columns == 0 {
+            bail!("'grid_columns' must be at least 1");
+        }
+        for (name, fraction) in [
+            ("width_fraction", self.width_fraction),
+            ("height_fraction", self.height_fraction),
+        ] {
+            if fraction.is_some_and(|f| !(f > 0.0 && f <= 1.0)) {
+                bail!("'{name}' must be greater than 0 and at most 1");
+            }
+        }
+        if !(0.0..=1.0).contains(&self.opacity) {
+            eprintln!("Warning: 'opacity' must be between 0 and 1");
+            self.opacity = self.opacity.clamp(0.0, 1.0);
+        }
+        if let Some(cwd) = &self.cwd {
+            self.cwd = Some(expand_home(cwd));
+        }
+        // Included files can use the colors of the palette as well.
+        let palette = self.palette.as_deref().map(palette::load).transpose()?;
+        color::with_palette(palette, || {
+            resolve_includes(&mut self.menu, &mut vec![config_path.clone()])
+        })?;
+        check_keep_open(&self.menu, self.keep_open_default)?;
+        assign_auto_keys(&mut self.menu, self.auto_keys)?;
+        if self.expand_env {
+            expand_env_in_entries(&mut self.menu)?;
+        }
+        self.path = config_path;
+        Ok(self)
+    }
+
+    /// Like `new`, but use the default config if the file does not exist.
+    pub fn new_or_default(name: impl AsRef<Path>) -> Result<Self> {
+        if resolve_path(&name)?.exists() {
+            Self::new(name)
+        } else {
+            Ok(Self::default())
+        }
+    }
 
+    fn read(config_path: &Path) -> Result<Self> {
         if !config_path.exists() {
             bail!("config file not found: {}", config_path.display());
         }
 
         let config_str = read_to_string(config_path).context("Failed to read configuration")?;
+        Self::parse(&config_str, config_path.extension().and_then(OsStr::to_str))
+    }
+
+    /// Parse a config in the format given by the file `extension`, YAML by default.
+    fn parse(config_str: &str, extension: Option<&str>) -> Result<Self> {
+        let palette = palette::load_for_config(config_str, extension)?;
+        let config = color::with_palette(palette, || match extension {
+            Some("json") => {
+                serde_json::from_str(config_str).context("Failed to deserialize configuration")
+            }
+            Some("toml") => {
+                toml::from_str(config_str).context("Failed to deserialize configuration")
+            }
+            _ => Self::read_yaml(config_str),
+        });
+        config.map_err(|err| match suggest_field(&err) {
+            Some(suggestion) => err.context(suggestion),
+            None => err,
+        })
+    }
 
-        match serde_yaml::from_str::<Self>(&config_str)
+    /// Parse a YAML config, falling back to the old config format.
+    fn read_yaml(config_str: &str) -> Result<Self> {
+        let merged = merge_yaml(config_str)?;
+        match from_yaml::<Self>(config_str, merged.as_ref())
             .context("Failed to deserialize configuration")
         {
             Ok(config) => Ok(config),
-            Err(err) => match serde_yaml::from_str::<compat::Config>(&config_str) {
+            Err(err) => match from_yaml::<compat::Config>(config_str, merged.as_ref()) {
                 Ok(compat) => {
                     eprintln!(
                         "Warning: using the old config format, which will be removed in a future version."
                     );
                     Ok(compat.into())
                 }
+                // The old format has a map of keys to entries instead of a list of entries.
+                Err(compat_err) if menu_is_map(config_str) => {
+                    Err(compat_err).context("Failed to deserialize configuration in the old format")
+                }
                 Err(_compat_err) => Err(err),
             },
         }
@@ -103,10 +357,1124 @@ impl Config {
     pub fn column_padding(&self) -> f64 {
         self.column_padding.unwrap_or_else(|| self.padding())
     }
+
+    /// Background of the given row, counting from zero at the top of each column.
+    pub fn row_background(&self, row: usize) -> Option<Color> {
+        match row % 2 {
+            0 => self.row_background_even,
+            _ => self.row_background_odd,
+        }
+    }
+
+    /// Margins of the layer surface anchored to `anchor`, as top, right, bottom and left margins.
+    ///
+    /// The offsets move the menu away from the anchored edges: `offset_y` down from the top edge
+    /// or up from the bottom one, `offset_x` right from the left edge or left from the right one.
+    /// Margins of edges the menu is not anchored to have no effect.
+    pub fn margins(&self, anchor: ConfigAnchor) -> [i32; 4] {
+        let anchor = Anchor::from(anchor);
+        let offset = |edge, offset| if anchor.contains(edge) { offset } else { 0 };
+        [
+            self.margin_top + offset(Anchor::TOP, self.offset_y),
+            self.margin_right + offset(Anchor::RIGHT, self.offset_x),
+            self.margin_bottom + offset(Anchor::BOTTOM, self.offset_y),
+            self.margin_left + offset(Anchor::LEFT, self.offset_x),
+        ]
+    }
+
+    /// Size of the menu set by `width_fraction` and `height_fraction` on an output of the given
+    /// logical size. The shadow is drawn within that size.
+    pub fn fraction_size(&self, (width, height): (i32, i32)) -> (Option<f64>, Option<f64>) {
+        let [top, right, bottom, left] = self.shadow_margins();
+        let size = |fraction: f64, output: i32, shadow: f64| {
+            ((output as f64 * fraction).floor() - shadow).max(0.0)
+        };
+        (
+            self.width_fraction.map(|f| size(f, width, left + right)),
+            self.height_fraction.map(|f| size(f, height, top + bottom)),
+        )
+    }
+
+    /// Room around the menu needed for the shadow, as top, right, bottom and left margins.
+    pub fn shadow_margins(&self) -> [f64; 4] {
+        let Some(shadow) = &self.shadow else {
+            return [0.0; 4];
+        };
+        let blur = shadow.blur.max(0.0);
+        [
+            blur - shadow.offset_y,
+            blur + shadow.offset_x,
+            blur + shadow.offset_y,
+            blur - shadow.offset_x,
+        ]
+        .map(|margin| margin.max(0.0).ceil())
+    }
+
+    /// Widths of the top, right, bottom and left sides of the border.
+    pub fn border_widths(&self) -> [f64; 4] {
+        let widths = &self.border_widths;
+        [widths.top, widths.right, widths.bottom, widths.left]
+            .map(|width| width.unwrap_or(self.border_width))
+    }
+
+    /// Space between the edges of the menu and its contents, as top, right, bottom and left
+    /// insets.
+    pub fn insets(&self) -> [f64; 4] {
+        self.border_widths().map(|width| width + self.padding())
+    }
+
+    /// Radii of the top-left, top-right, bottom-right and bottom-left corners.
+    pub fn corner_radii(&self) -> [f64; 4] {
+        let radii = &self.corner_radii;
+        [
+            radii.top_left,
+            radii.top_right,
+            radii.bottom_right,
+            radii.bottom_left,
+        ]
+        .map(|r| r.unwrap_or(self.corner_r))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Shadow {
+    pub color: Color,
+    /// How far the shadow fades out, in pixels.
+    pub blur: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            color: Color::from_rgba_hex(0x00000080),
+            blur: 10.0,
+            offset_x: 0.0,
+            offset_y: 4.0,
+        }
+    }
+}
+
+/// Per-corner overrides of `corner_r`.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct CornerRadii {
+    pub top_left: Option<f64>,
+    pub top_right: Option<f64>,
+    pub bottom_right: Option<f64>,
+    pub bottom_left: Option<f64>,
+}
+
+/// Per-side overrides of `border_width`.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct BorderWidths {
+    pub top: Option<f64>,
+    pub right: Option<f64>,
+    pub bottom: Option<f64>,
+    pub left: Option<f64>,
+}
+
+/// How entries are split into columns of at most `rows_per_column` rows.
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Columns {
+    /// Fill each column before starting the next one.
+    #[default]
+    Fill,
+    /// Use as many columns as `Fill`, with the rows spread evenly among them.
+    Auto,
+}
+
+/// Horizontal alignment of the descriptions. Mirrored with `auto_rtl`.
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Vertical alignment of the contents of the menu.
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerticalAlign {
+    #[default]
+    Top,
+    Center,
+    Bottom,
+}
+
+/// Style of the modifiers of keys.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModifierStyle {
+    /// Like the rest of the key.
+    #[default]
+    Plain,
+    /// Faded out.
+    Dim,
+    Bold,
+    /// In a smaller font.
+    Small,
+}
+
+/// How entries are arranged.
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum MenuLayout {
+    /// Rows of key, separator and description, split into columns by `rows_per_column`.
+    #[default]
+    List,
+    /// Rows of `grid_columns` equal tiles with the icon above the description and the key in the
+    /// corner. Separators are not shown.
+    Grid,
+}
+
+/// For an error about an unknown field, a message naming the closest expected field, if any is
+/// close enough to be a typo.
+fn suggest_field(err: &anyhow::Error) -> Option<String> {
+    let message = err.chain().last()?.to_string();
+    let (_, rest) = message.split_once("unknown field `")?;
+    // The unknown field is followed by the expected ones, all quoted with backticks.
+    let mut quoted = rest.split('`').step_by(2);
+    let field = quoted.next()?;
+    let (distance, closest) = quoted
+        .map(|expected| (edit_distance(field, expected), expected))
+        .min()?;
+    (distance <= 2).then(|| format!("Unknown field `{field}`, did you mean `{closest}`?"))
+}
+
+/// Number of single-character insertions, deletions or substitutions turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The YAML document with its `<<` merge keys applied, which serde_yaml does not do by itself,
+/// or `None` if it has none.
+fn merge_yaml(config_str: &str) -> Result<Option<serde_yaml::Value>> {
+    // Syntax errors are reported with their location when parsing the config itself.
+    let Ok(mut value) = serde_yaml::from_str::<serde_yaml::Value>(config_str) else {
+        return Ok(None);
+    };
+    let original = value.clone();
+    value
+        .apply_merge()
+        .context("Failed to apply '<<' merge keys")?;
+    Ok((value != original).then_some(value))
+}
+
+/// Deserialize `config_str`, or `merged` if given. Only errors in the former have a location.
+fn from_yaml<T: DeserializeOwned>(
+    config_str: &str,
+    merged: Option<&serde_yaml::Value>,
+) -> serde_yaml::Result<T> {
+    match merged {
+        Some(merged) => T::deserialize(merged),
+        None => serde_yaml::from_str(config_str),
+    }
+}
+
+fn menu_is_map(config_str: &str) -> bool {
+    serde_yaml::from_str::<serde_yaml::Value>(config_str)
+        .ok()
+        .and_then(|value| value.get("menu").map(serde_yaml::Value::is_mapping))
+        .unwrap_or(false)
+}
+
+fn default_shell() -> Vec<String> {
+    vec!["sh".into(), "-c".into()]
+}
+
+pub fn default_quit_keys() -> Vec<SingleKey> {
+    let ctrl = ModifierState {
+        mod_ctrl: true,
+        ..ModifierState::default()
+    };
+    [
+        (Keysym::Escape, "Escape", ModifierState::default()),
+        (Keysym::bracketleft, "Ctrl+bracketleft", ctrl),
+        (Keysym::g, "Ctrl+g", ctrl),
+    ]
+    .into_iter()
+    .map(|(keysym, repr, modifiers)| SingleKey {
+        keysym,
+        repr: repr.to_owned(),
+        modifiers,
+    })
+    .collect()
+}
+
+/// Extensions of the supported config formats, in the order they are tried for a config name
+/// without one.
+const EXTENSIONS: [&str; 4] = ["yaml", "yml", "json", "toml"];
+
+/// Resolve a config name to a file in the config directory. Absolute paths are used as-is.
+///
+/// Without a known extension, the first existing file among the `EXTENSIONS` is used, or the
+/// `.yaml` one if there is none.
+fn resolve_path(name: impl AsRef<Path>) -> Result<PathBuf> {
+    let mut config_path = config_dir().context("Cound not find config directory")?;
+    config_path.push("wlr-which-key");
+    config_path.push(name);
+    if config_path
+        .extension()
+        .is_some_and(|ext| EXTENSIONS.iter().any(|known| ext == *known))
+    {
+        return Ok(config_path);
+    }
+    let candidates = EXTENSIONS.map(|ext| config_path.with_extension(ext));
+    Ok(candidates
+        .iter()
+        .find(|path| path.exists())
+        .unwrap_or(&candidates[0])
+        .clone())
+}
+
+/// Replace all `include` entries with submenus read from the referenced files.
+///
+/// `stack` holds the files currently being included, starting with the root config file.
+fn resolve_includes(entries: &mut [Entry], stack: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in entries {
+        match entry {
+            Entry::Cmd { .. }
+            | Entry::Reload { .. }
+            | Entry::Copy { .. }
+            | Entry::Separator { .. }
+            | Entry::Dynamic { .. } => (),
+            Entry::Recursive { submenu, .. } => resolve_includes(submenu, stack)?,
+            Entry::Include {
+                key,
+                desc,
+                title,
+                anchor,
+                include,
+                color,
+                icon,
+                font,
+            } => {
+                let path = resolve_path(&include)?;
+                if let Some(start) = stack.iter().position(|p| *p == path) {
+                    let cycle: Vec<_> = stack[start..]
+                        .iter()
+                        .chain([&path])
+                        .map(|p| p.display().to_string())
+                        .collect();
+                    bail!("include cycle detected: {}", cycle.join(" -> "));
+                }
+
+                let mut included = Config::read(&path)
+                    .with_context(|| format!("Failed to include {}", path.display()))?;
+                stack.push(path);
+                resolve_includes(&mut included.menu, stack)?;
+                stack.pop();
+
+                *entry = Entry::Recursive {
+                    key: key.clone(),
+                    submenu: included.menu,
+                    cmd: None,
+                    desc: desc.clone(),
+                    title: title.take(),
+                    anchor: *anchor,
+                    color: *color,
+                    icon: icon.take(),
+                    font: font.take(),
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the commands with options which need the menu to stay open, but leave `keep_open` to
+/// `keep_open_default`.
+fn check_keep_open(entries: &[Entry], keep_open_default: bool) -> Result<()> {
+    for entry in entries {
+        match entry {
+            Entry::Cmd {
+                desc,
+                keep_open,
+                show_output,
+                repeat,
+                ..
+            } => {
+                if keep_open.unwrap_or(keep_open_default) {
+                    continue;
+                }
+                if *show_output {
+                    bail!("'show_output' requires 'keep_open' in entry '{desc}'");
+                }
+                if *repeat {
+                    bail!("'repeat' requires 'keep_open' in entry '{desc}'");
+                }
+            }
+            Entry::Recursive { submenu, .. } => check_keep_open(submenu, keep_open_default)?,
+            Entry::Reload { .. }
+            | Entry::Copy { .. }
+            | Entry::Separator { .. }
+            | Entry::Dynamic { .. }
+            | Entry::Include { .. } => (),
+        }
+    }
+    Ok(())
+}
+
+/// Keys given to entries without one with `auto_keys`, in order.
+const AUTO_KEYS: &str = "1234567890abcdefghijklmnopqrstuvwxyz";
+
+/// Give the entries without a key the first of `AUTO_KEYS` which no entry of the same menu is
+/// bound to, or fail if `enabled` is not set.
+fn assign_auto_keys(entries: &mut [Entry], enabled: bool) -> Result<()> {
+    let mut taken: Vec<Key> = entries
+        .iter()
+        .filter_map(Entry::key)
+        .filter(|key| !key.is_empty())
+        .cloned()
+        .collect();
+    let mut candidates = AUTO_KEYS.chars().map(|c| {
+        c.to_string()
+            .parse::<SingleKey>()
+            .expect("auto keys are valid key names")
+    });
+    for entry in entries.iter_mut() {
+        let desc = entry.desc().to_owned();
+        if let Some(key) = entry.key_mut()
+            && key.is_empty()
+        {
+            if !enabled {
+                bail!("'key' is required in entry '{desc}', or set 'auto_keys'");
+            }
+            let auto_key = candidates
+                .find(|candidate| {
+                    !taken
+                        .iter()
+                        .any(|key| key.matches_specific(candidate.keysym, candidate.modifiers))
+                })
+                .with_context(|| format!("no key left for entry '{desc}' with 'auto_keys'"))?;
+            let exact_modifiers = key.exact_modifiers();
+            *key = auto_key.into();
+            key.set_exact_modifiers(exact_modifiers);
+            taken.push(key.clone());
+        }
+        if let Entry::Recursive { submenu, .. } = entry {
+            assign_auto_keys(submenu, enabled)?;
+        }
+    }
+    Ok(())
+}
+
+fn expand_env_in_entries(entries: &mut [Entry]) -> Result<()> {
+    for entry in entries {
+        match entry {
+            Entry::Cmd { cmd, .. } => {
+                for cmd in cmd {
+                    *cmd = expand_env(cmd)?;
+                }
+            }
+            Entry::Dynamic { cmd, .. } => *cmd = expand_env(cmd)?,
+            Entry::Recursive { submenu, cmd, .. } => {
+                for cmd in cmd.iter_mut().flatten() {
+                    *cmd = expand_env(cmd)?;
+                }
+                expand_env_in_entries(submenu)?;
+            }
+            Entry::Copy { text, .. } => *text = expand_env(text)?,
+            Entry::Reload { .. } | Entry::Separator { .. } | Entry::Include { .. } => (),
+        }
+    }
+    Ok(())
+}
+
+/// Expand `${VAR}` references. `$VAR` without braces is left for the shell to expand.
+fn expand_env(cmd: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(cmd.len());
+    let mut rest = cmd;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .with_context(|| format!("unterminated '${{' in command '{cmd}'"))?;
+        let name = &after[..end];
+        let value = env::var(name).with_context(|| {
+            format!("environment variable '{name}' used in command '{cmd}' is not set")
+        })?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Replace a leading `~` with the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+/// Set by `set_config_dir`, used instead of `$XDG_CONFIG_HOME` for all config files.
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Look for config files in `dir`, instead of `$XDG_CONFIG_HOME` or `~/.config`. Only the first
+/// call has an effect.
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR.set(dir);
 }
 
 fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = CONFIG_DIR.get() {
+        return Some(dir.clone());
+    }
     env::var_os("XDG_CONFIG_HOME")
         .map(PathBuf::from)
         .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `menu` as the config `name` in a config directory under the temporary directory and
+    /// return its path.
+    fn write_config(name: &str, menu: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("wlr-which-key-test-{}", std::process::id()));
+        set_config_dir(dir.clone());
+        let dir = dir.join("wlr-which-key");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name).with_extension("yaml");
+        std::fs::write(&path, format!("menu: {menu}")).unwrap();
+        path
+    }
+
+    fn submenu(entry: &Entry) -> &[Entry] {
+        match entry {
+            Entry::Recursive { submenu, .. } => submenu,
+            _ => panic!("not a submenu"),
+        }
+    }
+
+    #[test]
+    fn include_file() {
+        let editors = write_config("editors", "[{ key: v, desc: Vim, cmd: vim }]");
+        let root = write_config(
+            "include_root",
+            &format!(
+                "[{{ key: e, desc: Editors, include: {} }}]",
+                editors.display()
+            ),
+        );
+        let config = Config::new(&root).unwrap();
+        assert_eq!(config.menu[0].desc(), "Editors");
+        assert_eq!(submenu(&config.menu[0])[0].desc(), "Vim");
+    }
+
+    #[test]
+    fn nested_include() {
+        let inner = write_config("inner", "[{ key: a, desc: A, cmd: a }]");
+        let outer = write_config(
+            "outer",
+            &format!("[{{ key: i, desc: Inner, include: {} }}]", inner.display()),
+        );
+        let root = write_config(
+            "nested_root",
+            &format!("[{{ key: o, desc: Outer, include: {} }}]", outer.display()),
+        );
+        let config = Config::new(&root).unwrap();
+        let inner = submenu(&submenu(&config.menu[0])[0]);
+        assert_eq!(inner[0].desc(), "A");
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = write_config("cycle_a", "[]").with_file_name("");
+        let (a, b) = (dir.join("cycle_a.yaml"), dir.join("cycle_b.yaml"));
+        write_config(
+            "cycle_a",
+            &format!("[{{ key: b, desc: B, include: {} }}]", b.display()),
+        );
+        write_config(
+            "cycle_b",
+            &format!("[{{ key: a, desc: A, include: {} }}]", a.display()),
+        );
+        let err = Config::new(&a).err().unwrap();
+        let (a, b) = (a.display(), b.display());
+        assert_eq!(
+            format!("{err:#}"),
+            format!("include cycle detected: {a} -> {b} -> {a}")
+        );
+    }
+
+    #[test]
+    fn expand_env_braces() {
+        let path = env::var("PATH").unwrap();
+        assert_eq!(
+            expand_env("echo ${PATH} done").unwrap(),
+            format!("echo {path} done")
+        );
+    }
+
+    #[test]
+    fn expand_env_unset() {
+        let err = expand_env("echo ${WLR_WHICH_KEY_TEST_UNSET}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "environment variable 'WLR_WHICH_KEY_TEST_UNSET' used in command 'echo ${WLR_WHICH_KEY_TEST_UNSET}' is not set"
+        );
+        assert!(expand_env("echo ${PATH").is_err());
+    }
+
+    #[test]
+    fn expand_env_literal() {
+        assert_eq!(expand_env("notify-send hi").unwrap(), "notify-send hi");
+        // Left for the shell.
+        assert_eq!(expand_env("echo $PATH").unwrap(), "echo $PATH");
+    }
+
+    #[test]
+    fn empty_shell() {
+        let path = write_config("empty_shell", "[]");
+        std::fs::write(&path, "shell: []\nmenu: []").unwrap();
+        let err = Config::new(&path).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "'shell' must contain at least the program to run"
+        );
+    }
+
+    #[test]
+    fn cwd_expands_home() {
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        assert_eq!(expand_home(Path::new("~/projects")), home.join("projects"));
+        assert_eq!(expand_home(Path::new("/tmp/~")), Path::new("/tmp/~"));
+    }
+
+    #[test]
+    fn missing_config_uses_default() {
+        let path = env::temp_dir().join("wlr-which-key-nonexistent.yaml");
+        let config = Config::new_or_default(&path).unwrap();
+        assert!(config.menu.is_empty());
+        assert!(Config::new(&path).is_err());
+    }
+
+    #[test]
+    fn error_of_matching_format() {
+        // A list of entries is the current format.
+        let path = write_config("bad_new_format", "[{ key: a, desc: A }]");
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(
+            err.starts_with("Failed to deserialize configuration: "),
+            "{err}"
+        );
+        assert!(err.contains("is required"), "{err}");
+
+        // A map of keys to entries is the old one.
+        let path = write_config("bad_old_format", "{ a: { desc: A } }");
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(
+            err.starts_with("Failed to deserialize configuration in the old format: "),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn copy_entry() {
+        let path = write_config("copy", "[]");
+        std::fs::write(
+            &path,
+            "expand_env: true\nmenu: [{ key: h, desc: Home, copy: '${HOME}/notes' }]",
+        )
+        .unwrap();
+        let config = Config::new(&path).unwrap();
+        let home = env::var("HOME").unwrap();
+        assert!(
+            matches!(&config.menu[0], Entry::Copy { text, .. } if *text == format!("{home}/notes"))
+        );
+
+        let err = serde_yaml::from_str::<Config>("menu: [{ key: c, desc: C, copy: a, cmd: a }]")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("'copy' cannot be combined with 'include', 'submenu' or 'cmd'")
+        );
+    }
+
+    #[test]
+    fn opacity_is_clamped() {
+        let path = write_config("opacity", "[]");
+        std::fs::write(&path, "opacity: 1.5\nmenu: []").unwrap();
+        assert_eq!(Config::new(&path).unwrap().opacity, 1.0);
+        std::fs::write(&path, "opacity: 0.5\nmenu: []").unwrap();
+        assert_eq!(Config::new(&path).unwrap().opacity, 0.5);
+    }
+
+    #[test]
+    fn corner_radii_fall_back_to_corner_r() {
+        let path = write_config("corner_radii", "[]");
+        std::fs::write(
+            &path,
+            "corner_r: 5\ncorner_radii: { top_left: 0, bottom_right: 12 }\nmenu: []",
+        )
+        .unwrap();
+        let config = Config::new(&path).unwrap();
+        assert_eq!(config.corner_radii(), [0.0, 5.0, 12.0, 5.0]);
+    }
+
+    #[test]
+    fn row_background_alternates() {
+        let config: Config =
+            serde_yaml::from_str("row_background_odd: '#ff0000'\nmenu: []").unwrap();
+        assert!(config.row_background(0).is_none());
+        assert_eq!(
+            format!("{:?}", config.row_background(3)),
+            format!("{:?}", Some(Color::from_rgba_hex(0xff0000ff)))
+        );
+    }
+
+    #[test]
+    fn separator_with_key() {
+        let path = write_config("separator_with_key", "[{ key: a, separator: true }]");
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(
+            err.contains("separators cannot have a 'key' or 'desc'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn json_config() {
+        let yaml = write_config("json_equals_yaml", "[{ key: a, desc: A, cmd: echo }]");
+        let json = yaml.with_extension("json");
+        std::fs::write(
+            &json,
+            r#"{ "menu": [{ "key": "a", "desc": "A", "cmd": "echo" }] }"#,
+        )
+        .unwrap();
+        let (yaml, json) = (Config::new(&yaml).unwrap(), Config::new(&json).unwrap());
+        assert!(matches!(&json.menu[..], [Entry::Cmd { cmd, .. }] if *cmd == ["echo"]));
+        assert_eq!(json.menu[0].desc(), yaml.menu[0].desc());
+        assert_eq!(json.font.0, yaml.font.0);
+    }
+
+    #[test]
+    fn resolve_extension() {
+        let yaml = write_config("resolve_extension", "[]");
+        let json = yaml.with_extension("json");
+        std::fs::write(&json, "{}").unwrap();
+        let name = yaml.with_extension("");
+        assert_eq!(resolve_path(&name).unwrap(), yaml);
+        std::fs::remove_file(&yaml).unwrap();
+        assert_eq!(resolve_path(&name).unwrap(), json);
+        std::fs::remove_file(&json).unwrap();
+        assert_eq!(resolve_path(&name).unwrap(), yaml);
+    }
+
+    #[test]
+    fn toml_config() {
+        let toml = write_config("toml_config", "[]").with_extension("toml");
+        std::fs::write(
+            &toml,
+            "font = \"monospace 12\"\n\n[[menu]]\nkey = \"a\"\ndesc = \"A\"\ncmd = \"echo\"\n",
+        )
+        .unwrap();
+        let config = Config::new(&toml).unwrap();
+        assert_eq!(config.font.0.to_string(), "monospace 12");
+        assert!(matches!(&config.menu[..], [Entry::Cmd { cmd, .. }] if *cmd == ["echo"]));
+    }
+
+    #[test]
+    fn default_config_round_trip() {
+        let yaml = serde_yaml::to_string(&Config::default()).unwrap();
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(serde_yaml::to_string(&config).unwrap(), yaml);
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let yaml = "- key: a
+  desc: A
+  submenu:
+  - key: b
+    desc: B
+    cmd: b
+    keep_open: true
+- separator: Tools
+- key: r
+  desc: Reload
+  reload: true
+- key: c
+  desc: Copy
+  copy: text
+  color: '#ff000080'
+";
+        let entries: Vec<Entry> = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(serde_yaml::to_string(&entries).unwrap(), yaml);
+    }
+
+    #[test]
+    fn layer_values() {
+        use smithay_client_toolkit::shell::wlr_layer::Layer;
+
+        let layer = |value: &str| -> Layer {
+            let config: Config = serde_yaml::from_str(&format!("layer: {value}")).unwrap();
+            config.layer.into()
+        };
+        assert_eq!(layer("background"), Layer::Background);
+        assert_eq!(layer("bottom"), Layer::Bottom);
+        assert_eq!(layer("top"), Layer::Top);
+        assert_eq!(layer("overlay"), Layer::Overlay);
+        assert_eq!(Layer::from(Config::default().layer), Layer::Overlay);
+        assert!(serde_yaml::from_str::<Config>("layer: middle").is_err());
+    }
+
+    #[test]
+    fn margins_offset_anchored_edges() {
+        let anchors = [
+            ConfigAnchor::Center,
+            ConfigAnchor::Top,
+            ConfigAnchor::Bottom,
+            ConfigAnchor::Left,
+            ConfigAnchor::Right,
+            ConfigAnchor::TopLeft,
+            ConfigAnchor::TopRight,
+            ConfigAnchor::BottomLeft,
+            ConfigAnchor::BottomRight,
+        ];
+        let margins = anchors.map(|anchor| {
+            Config {
+                margin_top: 1,
+                margin_right: 2,
+                margin_bottom: 3,
+                margin_left: 4,
+                offset_x: 10,
+                offset_y: 20,
+                ..Config::default()
+            }
+            .margins(anchor)
+        });
+        assert_eq!(
+            margins,
+            [
+                [1, 2, 3, 4],
+                [21, 2, 3, 4],
+                [1, 2, 23, 4],
+                [1, 2, 3, 14],
+                [1, 12, 3, 4],
+                [21, 2, 3, 14],
+                [21, 12, 3, 4],
+                [1, 2, 23, 14],
+                [1, 12, 23, 4],
+            ]
+        );
+    }
+
+    #[test]
+    fn confirm_only_for_commands() {
+        let path = write_config(
+            "confirm_submenu",
+            "[{ key: a, desc: A, confirm: Sure?, submenu: [] }]",
+        );
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(
+            err.contains("'confirm' is only allowed for commands"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn show_output_requires_keep_open() {
+        let path = write_config(
+            "show_output",
+            "[{ key: a, desc: A, cmd: a, show_output: true }]",
+        );
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'show_output' requires 'keep_open'"), "{err}");
+    }
+
+    #[test]
+    fn repeat_requires_keep_open() {
+        let path = write_config("repeat", "[{ key: a, desc: A, cmd: a, repeat: true }]");
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'repeat' requires 'keep_open'"), "{err}");
+    }
+
+    #[test]
+    fn cmd_scalar_or_list() {
+        let entries: Vec<Entry> =
+            serde_yaml::from_str("[{ key: a, desc: A, cmd: a }, { key: b, desc: B, cmd: [b, c] }]")
+                .unwrap();
+        assert!(matches!(&entries[0], Entry::Cmd { cmd, .. } if *cmd == ["a"]));
+        assert!(matches!(&entries[1], Entry::Cmd { cmd, .. } if *cmd == ["b", "c"]));
+        assert_eq!(
+            serde_yaml::to_string(&entries).unwrap(),
+            "- key: a\n  desc: A\n  cmd: a\n- key: b\n  desc: B\n  cmd:\n  - b\n  - c\n"
+        );
+        let path = write_config("empty_chain", "[{ key: a, desc: A, cmd: [] }]");
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'cmd' cannot be an empty list"), "{err}");
+    }
+
+    #[test]
+    fn names_resolve_in_config_dir() {
+        let editors = write_config("dir_editors", "[{ key: v, desc: Vim, cmd: vim }]");
+        assert_eq!(resolve_path("dir_editors").unwrap(), editors);
+        let root = write_config(
+            "dir_root",
+            "[{ key: e, desc: Editors, include: dir_editors }]",
+        );
+        let config = Config::new("dir_root").unwrap();
+        assert_eq!(config.path, root);
+        assert_eq!(submenu(&config.menu[0])[0].desc(), "Vim");
+    }
+
+    #[test]
+    fn yaml_str_config() {
+        write_config("str_editors", "[{ key: v, desc: Vim, cmd: vim }]");
+        let config =
+            Config::from_yaml_str("menu: [{ key: e, desc: Editors, include: str_editors }]")
+                .unwrap();
+        assert!(config.path.as_os_str().is_empty());
+        assert_eq!(submenu(&config.menu[0])[0].desc(), "Vim");
+        assert!(Config::from_yaml_str("shell: []\nmenu: []").is_err());
+    }
+
+    #[test]
+    fn exclusive_zone() {
+        assert_eq!(Config::default().exclusive_zone, None);
+        let config: Config = serde_yaml::from_str("exclusive_zone: -1\nmenu: []").unwrap();
+        assert_eq!(config.exclusive_zone, Some(-1));
+    }
+
+    #[test]
+    fn suggest_close_field() {
+        let err = Config::from_yaml_str("backgroud: '#000000'\nmenu: []")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Unknown field `backgroud`, did you mean `background`?"
+        );
+        // Too different to be a typo.
+        let err = Config::from_yaml_str("wallpaper: x\nmenu: []")
+            .err()
+            .unwrap();
+        assert!(!format!("{err:#}").contains("did you mean"));
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("color", "color"), 0);
+    }
+
+    #[test]
+    fn keep_open_default_allows_repeat() {
+        let path = write_config(
+            "keep_open_default",
+            "[{ key: a, desc: A, cmd: a, repeat: true }]",
+        );
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("keep_open_default: true\n{text}")).unwrap();
+        assert!(Config::new(&path).is_ok());
+
+        std::fs::write(
+            &path,
+            "keep_open_default: true\nmenu: [{ key: a, desc: A, cmd: a, keep_open: false, repeat: true }]",
+        )
+        .unwrap();
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'repeat' requires 'keep_open'"), "{err}");
+    }
+
+    #[test]
+    fn max_entries_at_least_one() {
+        let path = write_config("max_entries", "[]");
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("max_entries: 0\n{text}")).unwrap();
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'max_entries' must be at least 1"), "{err}");
+    }
+
+    #[test]
+    fn yaml_merge_keys() {
+        let config = Config::from_yaml_str(
+            "menu:
+  - &term { key: t, desc: Terminal, cmd: foot, keep_open: true }
+  - { <<: *term, key: s, desc: Shell }",
+        )
+        .unwrap();
+        assert!(matches!(
+            &config.menu[1],
+            Entry::Cmd { desc, cmd, keep_open: Some(true), .. } if desc == "Shell" && cmd == &["foot"]
+        ));
+
+        let err = format!(
+            "{:#}",
+            Config::from_yaml_str("menu:\n  - { <<: 1, key: a, desc: A, cmd: a }")
+                .err()
+                .unwrap()
+        );
+        assert!(err.contains("Failed to apply '<<' merge keys"), "{err}");
+    }
+
+    #[test]
+    fn anchor_only_for_submenus() {
+        let path = write_config("anchor_cmd", "[{ key: a, desc: A, cmd: a, anchor: top }]");
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(
+            err.contains("'anchor' is only allowed for submenus"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn default_quit_keys() {
+        let parsed: Vec<SingleKey> = ["Escape", "Ctrl+bracketleft", "Ctrl+g"]
+            .into_iter()
+            .map(|key| key.parse().unwrap())
+            .collect();
+        assert!(Config::default().quit_keys == parsed);
+    }
+
+    #[test]
+    fn fraction_size_leaves_room_for_shadow() {
+        let config: Config = serde_yaml::from_str(
+            "width_fraction: 0.5\nshadow: { blur: 5, offset_x: 2, offset_y: 8 }\nmenu: []",
+        )
+        .unwrap();
+        // The shadow takes 10 pixels horizontally.
+        assert_eq!(config.fraction_size((1001, 800)), (Some(490.0), None));
+    }
+
+    #[test]
+    fn fraction_out_of_range() {
+        for fraction in ["0", "1.5", "-0.5"] {
+            let path = write_config("fraction", "[]");
+            let text = std::fs::read_to_string(&path).unwrap();
+            std::fs::write(&path, format!("height_fraction: {fraction}\n{text}")).unwrap();
+            let err = format!("{:#}", Config::new(&path).err().unwrap());
+            assert!(
+                err.contains("'height_fraction' must be greater than 0 and at most 1"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn min_size_above_max_size() {
+        let path = write_config("min_size", "[]");
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("min_width: 300\nmax_width: 200\n{text}")).unwrap();
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(
+            err.contains("'min_width' cannot be greater than 'max_width'"),
+            "{err}"
+        );
+        std::fs::write(&path, format!("min_height: 200\nmax_height: 200\n{text}")).unwrap();
+        assert!(Config::new(&path).is_ok());
+    }
+
+    #[test]
+    fn border_widths_fall_back_to_border_width() {
+        let config: Config = serde_yaml::from_str(
+            "border_width: 2\nborder_widths: { left: 6 }\npadding: 5\nmenu: []",
+        )
+        .unwrap();
+        assert_eq!(config.border_widths(), [2.0, 2.0, 2.0, 6.0]);
+        assert_eq!(config.insets(), [7.0, 7.0, 7.0, 11.0]);
+    }
+
+    #[test]
+    fn negative_row_spacing() {
+        let path = write_config("row_spacing", "[]");
+        std::fs::write(&path, "row_spacing: -1\nmenu: []").unwrap();
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'row_spacing' cannot be negative"), "{err}");
+    }
+
+    #[test]
+    fn negative_key_desc_gap() {
+        let path = write_config("key_desc_gap", "[]");
+        std::fs::write(&path, "key_desc_gap: -1\nmenu: []").unwrap();
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("'key_desc_gap' cannot be negative"), "{err}");
+    }
+
+    #[test]
+    fn palette_colors_by_name() {
+        let path = write_config("palette_config", "[]");
+        let palette = path.with_file_name("palette.json");
+        std::fs::write(&palette, r##"{ "colors": { "color4": "#458588" } }"##).unwrap();
+        std::fs::write(
+            &path,
+            format!("palette: {}\nborder: color4\nmenu: []", palette.display()),
+        )
+        .unwrap();
+        let config = Config::new(&path).unwrap();
+        assert_eq!(
+            format!("{:?}", config.border),
+            format!("{:?}", "#458588".parse::<Color>().unwrap())
+        );
+
+        std::fs::write(
+            &path,
+            format!("palette: {}\nborder: color5\nmenu: []", palette.display()),
+        )
+        .unwrap();
+        let err = format!("{:#}", Config::new(&path).err().unwrap());
+        assert!(err.contains("neither a color of the palette"), "{err}");
+    }
+
+    #[test]
+    fn auto_keys_skip_taken_keys() {
+        let config = Config::from_yaml_str(
+            "auto_keys: true
+menu:
+  - { desc: First, cmd: a }
+  - { key: '2', desc: Two, cmd: b }
+  - { desc: Second, cmd: c }
+  - desc: Sub
+    submenu: [{ desc: Nested, cmd: d }]",
+        )
+        .unwrap();
+        let keys: Vec<_> = config
+            .menu
+            .iter()
+            .map(|entry| entry.key().unwrap().to_string())
+            .collect();
+        assert_eq!(keys, ["1", "2", "3", "4"]);
+        // Each menu has its own keys.
+        assert_eq!(submenu(&config.menu[3])[0].key().unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn key_required_without_auto_keys() {
+        let err = format!(
+            "{:#}",
+            Config::from_yaml_str("menu: [{ desc: A, cmd: a }]")
+                .err()
+                .unwrap()
+        );
+        assert!(
+            err.contains("'key' is required in entry 'A', or set 'auto_keys'"),
+            "{err}"
+        );
+    }
+}
@@ -1,11 +1,11 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use smithay_client_toolkit::shell::wlr_layer::Anchor;
 
 /// Light wrapper around `Anchor` which also supports the "no anchor" value.
 ///
 /// This type is also requires to derive `Deserialize` for the foreign type.
-#[derive(Deserialize, Default, Clone, Copy)]
-#[serde(rename_all(deserialize = "kebab-case"))]
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
 pub enum ConfigAnchor {
     #[default]
     Center,
@@ -82,14 +82,27 @@ impl From<Config> for super::Config {
                         keep_open,
                     } => super::Entry::Cmd {
                         key: key.into(),
-                        cmd,
+                        cmd: vec![cmd],
                         desc,
-                        keep_open,
+                        keep_open: Some(keep_open),
+                        keep_going: false,
+                        confirm: None,
+                        show_output: false,
+                        repeat: false,
+                        color: None,
+                        icon: None,
+                        font: None,
                     },
                     Entry::Recursive { submenu, desc } => super::Entry::Recursive {
                         key: key.into(),
                         submenu: map_entries(submenu),
+                        cmd: None,
                         desc,
+                        title: None,
+                        anchor: None,
+                        color: None,
+                        icon: None,
+                        font: None,
                     },
                 })
                 .collect()
@@ -109,11 +122,8 @@ impl From<Config> for super::Config {
             border_width: value.border_width,
             corner_r: value.corner_r,
             padding: value.padding,
-            rows_per_column: None,
-            column_padding: None,
             menu: map_entries(value.menu),
-            inhibit_compositor_keyboard_shortcuts: false,
-            auto_kbd_layout: false,
+            ..Self::default()
         }
     }
 }
@@ -1,59 +1,554 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::{Context, bail};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use super::{ConfigAnchor, Font};
+use crate::color::Color;
 use crate::key::Key;
 
-#[derive(Deserialize)]
-#[serde(try_from = "RawEntry")]
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(try_from = "RawEntry", into = "RawEntry")]
 pub enum Entry {
     Cmd {
         key: Key,
-        cmd: String,
+        /// Commands run in order, stopping at the first one that fails unless `keep_going` is set.
+        cmd: Vec<String>,
         desc: String,
-        keep_open: bool,
+        /// `None` to use `keep_open_default` from the config.
+        keep_open: Option<bool>,
+        keep_going: bool,
+        /// Ask for confirmation with this prompt before running the command.
+        confirm: Option<String>,
+        /// Wait for the command and show the last line of its output. Requires `keep_open`.
+        show_output: bool,
+        /// Run the command again at the key repeat rate while the key is held. Requires
+        /// `keep_open`.
+        repeat: bool,
+        color: Option<Color>,
+        icon: Option<PathBuf>,
+        font: Option<Font>,
     },
     Recursive {
         key: Key,
         submenu: Vec<Self>,
+        /// Commands run before the submenu is opened, as with `Cmd`.
+        cmd: Option<Vec<String>>,
         desc: String,
+        title: Option<String>,
+        /// Where to show the menu while this submenu is open, instead of the global `anchor`.
+        anchor: Option<ConfigAnchor>,
+        color: Option<Color>,
+        icon: Option<PathBuf>,
+        font: Option<Font>,
     },
+    /// Re-read the config file and go back to the top-level menu.
+    Reload {
+        key: Key,
+        desc: String,
+        color: Option<Color>,
+        icon: Option<PathBuf>,
+        font: Option<Font>,
+    },
+    /// Copy `text` to the clipboard.
+    Copy {
+        key: Key,
+        text: String,
+        desc: String,
+        color: Option<Color>,
+        icon: Option<PathBuf>,
+        font: Option<Font>,
+    },
+    /// A divider between groups of entries, with an optional label. Cannot be selected.
+    Separator { label: Option<String> },
+    /// Entries read from the output of `cmd`, one per line in the same form as `--from-stdin`.
+    /// The command runs when the menu is built, and again each time the menu containing it is
+    /// opened if `refresh` is set.
+    Dynamic { cmd: String, refresh: bool },
+    /// A submenu read from another config file. Replaced by `Recursive` when the config is loaded.
+    Include {
+        key: Key,
+        desc: String,
+        title: Option<String>,
+        anchor: Option<ConfigAnchor>,
+        include: String,
+        color: Option<Color>,
+        icon: Option<PathBuf>,
+        font: Option<Font>,
+    },
+}
+
+impl Entry {
+    /// The key of the entry, `None` for separators.
+    pub fn key(&self) -> Option<&Key> {
+        match self {
+            Self::Cmd { key, .. }
+            | Self::Recursive { key, .. }
+            | Self::Reload { key, .. }
+            | Self::Copy { key, .. }
+            | Self::Include { key, .. } => Some(key),
+            Self::Separator { .. } | Self::Dynamic { .. } => None,
+        }
+    }
+
+    pub fn key_mut(&mut self) -> Option<&mut Key> {
+        match self {
+            Self::Cmd { key, .. }
+            | Self::Recursive { key, .. }
+            | Self::Reload { key, .. }
+            | Self::Copy { key, .. }
+            | Self::Include { key, .. } => Some(key),
+            Self::Separator { .. } | Self::Dynamic { .. } => None,
+        }
+    }
+
+    pub fn desc(&self) -> &str {
+        match self {
+            Self::Cmd { desc, .. }
+            | Self::Recursive { desc, .. }
+            | Self::Reload { desc, .. }
+            | Self::Copy { desc, .. }
+            | Self::Include { desc, .. } => desc,
+            Self::Separator { label } => label.as_deref().unwrap_or_default(),
+            Self::Dynamic { .. } => "",
+        }
+    }
+
+    pub fn color(&self) -> Option<Color> {
+        match self {
+            Self::Cmd { color, .. }
+            | Self::Recursive { color, .. }
+            | Self::Reload { color, .. }
+            | Self::Copy { color, .. }
+            | Self::Include { color, .. } => *color,
+            Self::Separator { .. } | Self::Dynamic { .. } => None,
+        }
+    }
+
+    pub fn icon(&self) -> Option<&Path> {
+        match self {
+            Self::Cmd { icon, .. }
+            | Self::Recursive { icon, .. }
+            | Self::Reload { icon, .. }
+            | Self::Copy { icon, .. }
+            | Self::Include { icon, .. } => icon.as_deref(),
+            Self::Separator { .. } | Self::Dynamic { .. } => None,
+        }
+    }
+
+    pub fn font(&self) -> Option<&Font> {
+        match self {
+            Self::Cmd { font, .. }
+            | Self::Recursive { font, .. }
+            | Self::Reload { font, .. }
+            | Self::Copy { font, .. }
+            | Self::Include { font, .. } => font.as_ref(),
+            Self::Separator { .. } | Self::Dynamic { .. } => None,
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Default)]
 #[serde(deny_unknown_fields)]
 struct RawEntry {
-    key: Key,
-    desc: String,
-    cmd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<Key>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exact_modifiers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    separator: Option<RawSeparator>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dynamic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd: Option<RawCmd>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     keep_open: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_going: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    show_output: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeat: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     submenu: Option<Vec<Entry>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    reload: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    copy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anchor: Option<ConfigAnchor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    font: Option<Font>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawCmd {
+    Single(String),
+    Chain(Vec<String>),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawSeparator {
+    Plain(bool),
+    Label(String),
 }
 
 impl TryFrom<RawEntry> for Entry {
     type Error = anyhow::Error;
 
     fn try_from(value: RawEntry) -> Result<Self, Self::Error> {
-        if let Some(submenu) = value.submenu {
+        if value.exact_modifiers.is_some() && value.key.is_none() {
+            bail!("'exact_modifiers' requires a 'key'");
+        }
+        if let Some(separator) = value.separator {
+            if value.key.is_some() || value.desc.is_some() {
+                bail!("separators cannot have a 'key' or 'desc', use 'separator: <label>' instead");
+            }
+            let has_action = value.cmd.is_some()
+                || value.submenu.is_some()
+                || value.include.is_some()
+                || value.reload
+                || value.copy.is_some()
+                || value.dynamic.is_some();
+            let has_options = value.keep_open.is_some()
+                || value.title.is_some()
+                || value.anchor.is_some()
+                || value.color.is_some()
+                || value.icon.is_some()
+                || value.font.is_some()
+                || value.confirm.is_some()
+                || value.show_output.is_some()
+                || value.repeat.is_some()
+                || value.keep_going.is_some()
+                || value.refresh.is_some();
+            if has_action || has_options {
+                bail!("'separator' cannot be combined with other fields");
+            }
+            let label = match separator {
+                RawSeparator::Plain(true) => None,
+                RawSeparator::Plain(false) => bail!("'separator' must be 'true' or a label"),
+                RawSeparator::Label(label) => Some(label),
+            };
+            return Ok(Self::Separator { label });
+        }
+
+        if let Some(cmd) = value.dynamic {
+            if value.key.is_some() || value.desc.is_some() {
+                bail!("'dynamic' entries get their keys and descriptions from the command output");
+            }
+            let has_action = value.cmd.is_some()
+                || value.submenu.is_some()
+                || value.include.is_some()
+                || value.reload
+                || value.copy.is_some();
+            let has_options = value.keep_open.is_some()
+                || value.title.is_some()
+                || value.anchor.is_some()
+                || value.color.is_some()
+                || value.icon.is_some()
+                || value.font.is_some()
+                || value.confirm.is_some()
+                || value.show_output.is_some()
+                || value.repeat.is_some()
+                || value.keep_going.is_some();
+            if has_action || has_options {
+                bail!("'dynamic' can only be combined with 'refresh'");
+            }
+            return Ok(Self::Dynamic {
+                cmd,
+                refresh: value.refresh.unwrap_or(false),
+            });
+        }
+        if value.refresh.is_some() {
+            bail!("'refresh' is only allowed for 'dynamic' entries");
+        }
+        if value.confirm.is_some() && value.cmd.is_none() {
+            bail!("'confirm' is only allowed for commands");
+        }
+        if value.show_output.is_some() && value.cmd.is_none() {
+            bail!("'show_output' is only allowed for commands");
+        }
+        if value.repeat.is_some() && value.cmd.is_none() {
+            bail!("'repeat' is only allowed for commands");
+        }
+        if value.keep_going.is_some() && value.cmd.is_none() {
+            bail!("'keep_going' is only allowed for commands");
+        }
+        if value.anchor.is_some() && value.submenu.is_none() && value.include.is_none() {
+            bail!("'anchor' is only allowed for submenus");
+        }
+
+        // A missing key is assigned by `auto_keys`, or reported, once the config is loaded.
+        let mut key = value.key.unwrap_or_default();
+        key.set_exact_modifiers(value.exact_modifiers.unwrap_or(true));
+        let desc = value.desc.context("'desc' is required")?;
+        if value.reload {
+            if value.include.is_some() || value.submenu.is_some() || value.cmd.is_some() {
+                bail!("'reload' cannot be combined with 'include', 'submenu' or 'cmd'");
+            }
+            if value.copy.is_some() {
+                bail!("cannot have both 'reload' and 'copy'");
+            }
+            if value.keep_open.is_some() || value.title.is_some() {
+                bail!("'reload' cannot be combined with 'keep_open' or 'title'");
+            }
+            Ok(Self::Reload {
+                key,
+                desc,
+                color: value.color,
+                icon: value.icon,
+                font: value.font,
+            })
+        } else if let Some(text) = value.copy {
+            if value.include.is_some() || value.submenu.is_some() || value.cmd.is_some() {
+                bail!("'copy' cannot be combined with 'include', 'submenu' or 'cmd'");
+            }
+            if value.keep_open.is_some() || value.title.is_some() {
+                bail!("'copy' cannot be combined with 'keep_open' or 'title'");
+            }
+            Ok(Self::Copy {
+                key,
+                text,
+                desc,
+                color: value.color,
+                icon: value.icon,
+                font: value.font,
+            })
+        } else if let Some(include) = value.include {
+            if value.submenu.is_some() {
+                bail!("cannot have both 'include' and 'submenu'");
+            }
             if value.cmd.is_some() {
-                bail!("cannot have both 'submenu' and 'cmd'");
+                bail!("cannot have both 'include' and 'cmd'");
             }
+            if value.keep_open.is_some() {
+                bail!("cannot have both 'include' and 'keep_open'");
+            }
+            Ok(Self::Include {
+                key,
+                desc,
+                title: value.title,
+                anchor: value.anchor,
+                include,
+                color: value.color,
+                icon: value.icon,
+                font: value.font,
+            })
+        } else if let Some(submenu) = value.submenu {
             if value.keep_open.is_some() {
                 bail!("cannot have both 'submenu' and 'keep_open'");
             }
+            if value.confirm.is_some()
+                || value.show_output.is_some()
+                || value.repeat.is_some()
+                || value.keep_going.is_some()
+            {
+                bail!(
+                    "'confirm', 'show_output', 'repeat' and 'keep_going' cannot be combined with 'submenu'"
+                );
+            }
+            let cmd = match value.cmd {
+                None => None,
+                Some(RawCmd::Single(cmd)) => Some(vec![cmd]),
+                Some(RawCmd::Chain(cmds)) if cmds.is_empty() => {
+                    bail!("'cmd' cannot be an empty list")
+                }
+                Some(RawCmd::Chain(cmds)) => Some(cmds),
+            };
             Ok(Self::Recursive {
-                key: value.key,
+                key,
                 submenu,
-                desc: value.desc,
+                cmd,
+                desc,
+                title: value.title,
+                anchor: value.anchor,
+                color: value.color,
+                icon: value.icon,
+                font: value.font,
             })
         } else {
+            if value.title.is_some() {
+                bail!("'title' is only allowed for submenus");
+            }
+            // Without `keep_open`, this is checked once `keep_open_default` is known.
+            let keep_open = value.keep_open;
+            let show_output = value.show_output.unwrap_or(false);
+            if show_output && keep_open == Some(false) {
+                bail!("'show_output' requires 'keep_open'");
+            }
+            let repeat = value.repeat.unwrap_or(false);
+            if repeat && keep_open == Some(false) {
+                bail!("'repeat' requires 'keep_open'");
+            }
+            let cmd = match value
+                .cmd
+                .context("one of 'submenu', 'include', 'cmd', 'copy' or 'reload' is required")?
+            {
+                RawCmd::Single(cmd) => vec![cmd],
+                RawCmd::Chain(cmds) if cmds.is_empty() => bail!("'cmd' cannot be an empty list"),
+                RawCmd::Chain(cmds) => cmds,
+            };
             Ok(Self::Cmd {
-                key: value.key,
-                cmd: value
-                    .cmd
-                    .context("either or 'submenu' or 'cmd' is required")?,
-                desc: value.desc,
-                keep_open: value.keep_open.unwrap_or(false),
+                key,
+                cmd,
+                desc,
+                keep_open,
+                keep_going: value.keep_going.unwrap_or(false),
+                confirm: value.confirm,
+                show_output,
+                repeat,
+                color: value.color,
+                icon: value.icon,
+                font: value.font,
             })
         }
     }
 }
+
+impl From<Entry> for RawEntry {
+    fn from(value: Entry) -> Self {
+        let exact_modifiers = value
+            .key()
+            .filter(|key| !key.exact_modifiers())
+            .map(|_| false);
+        let raw = match value {
+            Entry::Cmd {
+                key,
+                mut cmd,
+                desc,
+                keep_open,
+                keep_going,
+                confirm,
+                show_output,
+                repeat,
+                color,
+                icon,
+                font,
+            } => Self {
+                key: Some(key),
+                desc: Some(desc),
+                cmd: Some(match cmd.len() {
+                    1 => RawCmd::Single(cmd.remove(0)),
+                    _ => RawCmd::Chain(cmd),
+                }),
+                keep_open,
+                keep_going: keep_going.then_some(true),
+                confirm,
+                show_output: show_output.then_some(true),
+                repeat: repeat.then_some(true),
+                color,
+                icon,
+                font,
+                ..Self::default()
+            },
+            Entry::Recursive {
+                key,
+                submenu,
+                cmd,
+                desc,
+                title,
+                anchor,
+                color,
+                icon,
+                font,
+            } => Self {
+                key: Some(key),
+                desc: Some(desc),
+                submenu: Some(submenu),
+                cmd: cmd.map(|mut cmd| match cmd.len() {
+                    1 => RawCmd::Single(cmd.remove(0)),
+                    _ => RawCmd::Chain(cmd),
+                }),
+                title,
+                anchor,
+                color,
+                icon,
+                font,
+                ..Self::default()
+            },
+            Entry::Reload {
+                key,
+                desc,
+                color,
+                icon,
+                font,
+            } => Self {
+                key: Some(key),
+                desc: Some(desc),
+                reload: true,
+                color,
+                icon,
+                font,
+                ..Self::default()
+            },
+            Entry::Copy {
+                key,
+                text,
+                desc,
+                color,
+                icon,
+                font,
+            } => Self {
+                key: Some(key),
+                desc: Some(desc),
+                copy: Some(text),
+                color,
+                icon,
+                font,
+                ..Self::default()
+            },
+            Entry::Separator { label } => Self {
+                separator: Some(match label {
+                    Some(label) => RawSeparator::Label(label),
+                    None => RawSeparator::Plain(true),
+                }),
+                ..Self::default()
+            },
+            Entry::Dynamic { cmd, refresh } => Self {
+                dynamic: Some(cmd),
+                refresh: refresh.then_some(true),
+                ..Self::default()
+            },
+            Entry::Include {
+                key,
+                desc,
+                title,
+                anchor,
+                include,
+                color,
+                icon,
+                font,
+            } => Self {
+                key: Some(key),
+                desc: Some(desc),
+                include: Some(include),
+                title,
+                anchor,
+                color,
+                icon,
+                font,
+                ..Self::default()
+            },
+        };
+        Self {
+            exact_modifiers,
+            ..raw
+        }
+    }
+}
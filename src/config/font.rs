@@ -1,8 +1,9 @@
 use std::fmt;
 
 use pangocairo::pango::FontDescription;
-use serde::de;
+use serde::{de, ser};
 
+#[derive(Clone)]
 pub struct Font(pub FontDescription);
 
 impl Font {
@@ -11,6 +12,15 @@ impl Font {
     }
 }
 
+impl ser::Serialize for Font {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.0.to_str())
+    }
+}
+
 impl<'de> de::Deserialize<'de> for Font {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::shell::wlr_layer::Layer;
+
+/// Light wrapper around `Layer`.
+///
+/// This type is required to derive `Deserialize` for the foreign type.
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigLayer {
+    Background,
+    Bottom,
+    Top,
+    #[default]
+    Overlay,
+}
+
+/// Convert this layer into the type expected by `sctk`.
+impl From<ConfigLayer> for Layer {
+    fn from(value: ConfigLayer) -> Self {
+        match value {
+            ConfigLayer::Background => Layer::Background,
+            ConfigLayer::Bottom => Layer::Bottom,
+            ConfigLayer::Top => Layer::Top,
+            ConfigLayer::Overlay => Layer::Overlay,
+        }
+    }
+}
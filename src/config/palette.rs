@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::expand_home;
+use crate::color::Color;
+
+/// Read the colors of a pywal `colors.json` or a base16 scheme, by name.
+///
+/// Every string value of the file which is a color, with or without a leading `#`, is taken,
+/// whatever its nesting, so that both `colors.color0` of pywal and `base00` of base16 are found
+/// as `color0` and `base00`.
+pub fn load(path: &Path) -> Result<HashMap<String, Color>> {
+    let path = expand_home(path);
+    let palette_str = read_to_string(&path)
+        .with_context(|| format!("Failed to read palette {}", path.display()))?;
+    // JSON is valid YAML, so both formats are read the same way.
+    let value = serde_yaml::from_str(&palette_str)
+        .with_context(|| format!("Failed to parse palette {}", path.display()))?;
+    let mut colors = HashMap::new();
+    collect_colors(&value, &mut colors);
+    Ok(colors)
+}
+
+fn collect_colors(value: &serde_yaml::Value, colors: &mut HashMap<String, Color>) {
+    let serde_yaml::Value::Mapping(mapping) = value else {
+        return;
+    };
+    for (name, value) in mapping {
+        match (name.as_str(), value.as_str()) {
+            (Some(name), Some(color)) => {
+                let color = match color.starts_with('#') {
+                    true => color.parse(),
+                    false => format!("#{color}").parse(),
+                };
+                if let Ok(color) = color {
+                    colors.insert(name.to_owned(), color);
+                }
+            }
+            _ => collect_colors(value, colors),
+        }
+    }
+}
+
+/// The `palette` field of a config, read before the rest of the config since its colors are
+/// needed to parse it.
+#[derive(Deserialize)]
+struct PaletteField {
+    palette: Option<std::path::PathBuf>,
+}
+
+/// The palette set in a config in the format given by the file `extension`, if any. Errors are
+/// left to the parsing of the whole config.
+pub fn load_for_config(
+    config_str: &str,
+    extension: Option<&str>,
+) -> Result<Option<HashMap<String, Color>>> {
+    let field: Option<PaletteField> = match extension {
+        Some("json") => serde_json::from_str(config_str).ok(),
+        Some("toml") => toml::from_str(config_str).ok(),
+        _ => serde_yaml::from_str(config_str).ok(),
+    };
+    field
+        .and_then(|field| field.palette)
+        .map(|path| load(&path))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colors as text, since opaque colors have a NaN alpha which never compares equal.
+    fn hex(color: &str) -> String {
+        format!("{:?}", color.parse::<Color>().unwrap())
+    }
+
+    fn colors(palette: &str) -> HashMap<String, Color> {
+        let mut colors = HashMap::new();
+        collect_colors(&serde_yaml::from_str(palette).unwrap(), &mut colors);
+        colors
+    }
+
+    #[test]
+    fn pywal_colors() {
+        let colors = colors(
+            r##"{
+                "wallpaper": "/home/user/wall.png",
+                "special": { "background": "#1d2021" },
+                "colors": { "color0": "#282828", "color1": "#cc241d" }
+            }"##,
+        );
+        let mut names: Vec<_> = colors.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["background", "color0", "color1"]);
+        assert_eq!(format!("{:?}", colors["color1"]), hex("#cc241d"));
+    }
+
+    #[test]
+    fn base16_colors() {
+        let colors = colors("scheme: Gruvbox\nbase00: \"282828\"\nbase08: fb4934");
+        assert_eq!(colors.len(), 2);
+        assert_eq!(format!("{:?}", colors["base08"]), hex("#fb4934"));
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use super::{Config, Entry};
+use crate::key::SingleKey;
+
+pub enum Problem {
+    /// A key is bound to more than one entry of the same menu, or an earlier entry catches every
+    /// press of it. Only the first one is used.
+    DuplicateKey(String),
+    /// All keys of an entry are bound to earlier entries.
+    Unreachable(String),
+    EmptyDescription(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateKey(msg) | Self::Unreachable(msg) | Self::EmptyDescription(msg) => {
+                f.write_str(msg)
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Look for mistakes which do not prevent the config from loading, such as entries that can
+    /// never be selected.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        check_entries(&self.menu, &mut Vec::new(), &mut problems);
+        problems
+    }
+
+    /// Print a warning for every key bound to more than one entry of the same menu.
+    pub fn warn_duplicate_keys(&self) {
+        for problem in self.problems() {
+            if let Problem::DuplicateKey(_) = problem {
+                eprintln!("Warning: {problem}");
+            }
+        }
+    }
+}
+
+fn check_entries<'a>(entries: &'a [Entry], path: &mut Vec<&'a str>, problems: &mut Vec<Problem>) {
+    let location = match path.is_empty() {
+        true => String::from("menu"),
+        false => format!("menu '{}'", path.join(" > ")),
+    };
+
+    let mut seen: Vec<(&SingleKey, bool, &str)> = Vec::new();
+    for entry in entries {
+        // Separators cannot be selected, so there is nothing to check.
+        let Some(entry_key) = entry.key() else {
+            continue;
+        };
+        let desc = entry.desc();
+        if desc.trim().is_empty() {
+            problems.push(Problem::EmptyDescription(format!(
+                "{location}: entry '{entry_key}' has an empty description"
+            )));
+        }
+
+        let exact = entry_key.exact_modifiers();
+        let mut reachable = false;
+        for key in entry_key.alternatives() {
+            match seen
+                .iter()
+                .find(|(other, other_exact, _)| other.catches_all_of(*other_exact, key, exact))
+            {
+                Some((_, _, other_desc)) => problems.push(Problem::DuplicateKey(format!(
+                    "{location}: key '{}' of '{desc}' is already used by '{other_desc}'",
+                    key.repr
+                ))),
+                None => {
+                    seen.push((key, exact, desc));
+                    reachable = true;
+                }
+            }
+        }
+        if !reachable {
+            problems.push(Problem::Unreachable(format!(
+                "{location}: '{desc}' is unreachable, all of its keys are used by earlier entries"
+            )));
+        }
+
+        if let Entry::Recursive { submenu, .. } = entry {
+            path.push(desc);
+            check_entries(submenu, path, problems);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(config: &str) -> Vec<String> {
+        serde_yaml::from_str::<Config>(config)
+            .unwrap()
+            .problems()
+            .iter()
+            .map(Problem::to_string)
+            .collect()
+    }
+
+    fn duplicates(config: &str) -> Vec<String> {
+        serde_yaml::from_str::<Config>(config)
+            .unwrap()
+            .problems()
+            .into_iter()
+            .filter_map(|problem| match problem {
+                Problem::DuplicateKey(msg) => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_config() {
+        let config = "menu:
+  - { key: a, desc: A, cmd: a }
+  - { key: b, desc: B, submenu: [{ key: a, desc: A, cmd: a }] }";
+        assert!(problems(config).is_empty());
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let config = "menu:
+  - { key: a, desc: First, cmd: a }
+  - { key: [a, b], desc: Second, cmd: b }
+  - { key: a, desc: Third, cmd: c }";
+        assert_eq!(
+            problems(config),
+            [
+                "menu: key 'a' of 'Second' is already used by 'First'",
+                "menu: key 'a' of 'Third' is already used by 'First'",
+                "menu: 'Third' is unreachable, all of its keys are used by earlier entries",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_empty_description() {
+        let config = "menu:
+  - key: p
+    desc: Power
+    submenu:
+      - key: s
+        desc: Sleep
+        submenu: [{ key: x, desc: ' ', cmd: x }]";
+        assert_eq!(
+            problems(config),
+            ["menu 'Power > Sleep': entry 'x' has an empty description"]
+        );
+    }
+
+    #[test]
+    fn same_key_twice() {
+        let config =
+            "menu:\n  - { key: a, desc: First, cmd: a }\n  - { key: a, desc: Second, cmd: a }";
+        assert_eq!(
+            duplicates(config),
+            ["menu: key 'a' of 'Second' is already used by 'First'"]
+        );
+    }
+
+    #[test]
+    fn modifiers_are_part_of_the_key() {
+        let config =
+            "menu:\n  - { key: a, desc: Plain, cmd: a }\n  - { key: Ctrl+a, desc: Ctrl, cmd: a }";
+        assert!(duplicates(config).is_empty());
+        let config = "menu:\n  - { key: Ctrl+a, desc: First, cmd: a }\n  - { key: Ctrl+a, desc: Second, cmd: a }";
+        assert_eq!(duplicates(config).len(), 1);
+    }
+
+    #[test]
+    fn only_duplicates_within_a_menu() {
+        let config = "menu:
+  - { key: a, desc: A, cmd: a }
+  - { key: b, desc: B, submenu: [{ key: a, desc: Nested, cmd: a }] }";
+        assert!(duplicates(config).is_empty());
+    }
+
+    #[test]
+    fn any_side_catches_one_side() {
+        let config = "menu:\n  - { key: Ctrl+a, desc: Any, cmd: a }\n  - { key: LCtrl+a, desc: Left, cmd: a }";
+        assert_eq!(duplicates(config).len(), 1);
+        let config = "menu:\n  - { key: LCtrl+a, desc: Left, cmd: a }\n  - { key: Ctrl+a, desc: Any, cmd: a }";
+        assert!(duplicates(config).is_empty());
+        let config = "menu:\n  - { key: LCtrl+a, desc: Left, cmd: a }\n  - { key: RCtrl+a, desc: Right, cmd: a }";
+        assert!(duplicates(config).is_empty());
+    }
+
+    #[test]
+    fn extra_modifiers() {
+        let config = "menu:\n  - { key: a, exact_modifiers: false, desc: Any, cmd: a }\n  - { key: Ctrl+a, desc: Ctrl, cmd: a }";
+        assert_eq!(duplicates(config).len(), 1);
+        let config = "menu:\n  - { key: a, desc: Plain, cmd: a }\n  - { key: a, exact_modifiers: false, desc: Any, cmd: a }";
+        assert!(duplicates(config).is_empty());
+        let config =
+            "menu:\n  - { key: a, desc: Plain, cmd: a }\n  - { key: Ctrl+a, desc: Ctrl, cmd: a }";
+        assert!(duplicates(config).is_empty());
+    }
+
+    #[test]
+    fn wildcard_only_catches_wildcard() {
+        let config = "menu:\n  - { key: '*', desc: Any, cmd: a }\n  - { key: a, desc: A, cmd: a }\n  - { key: '*', desc: Other, cmd: a }";
+        assert_eq!(
+            duplicates(config),
+            ["menu: key '*' of 'Other' is already used by 'Any'"]
+        );
+    }
+}
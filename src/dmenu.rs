@@ -0,0 +1,117 @@
+use std::io::BufRead;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result, bail};
+
+use crate::config::Entry;
+use crate::key::{Key, SingleKey};
+
+/// Read menu entries, one per line, in the form `key<TAB>description[<TAB>command]`.
+///
+/// Empty lines are skipped. If the command is missing, the description is used instead, so that
+/// `--print-selection` always has something to print.
+///
+/// `source` names the input in error messages.
+pub fn read_entries(input: impl BufRead, source: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (line_i, line) in input.lines().enumerate() {
+        let line = line.with_context(|| format!("Failed to read entries from {source}"))?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = parse_entry(&line).with_context(|| format!("{source} line {}", line_i + 1))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Run `cmd` with `shell` and read menu entries from its output, as with `read_entries`.
+pub fn run_entries(cmd: &str, shell: &[String], cwd: Option<&Path>) -> Result<Vec<Entry>> {
+    let mut proc = Command::new(&shell[0]);
+    proc.args(&shell[1..]);
+    proc.arg(cmd);
+    if let Some(cwd) = cwd.filter(|cwd| cwd.is_dir()) {
+        proc.current_dir(cwd);
+    }
+    proc.stdin(Stdio::null());
+    proc.stderr(Stdio::inherit());
+    let output = proc
+        .output()
+        .with_context(|| format!("Failed to run '{cmd}'"))?;
+    if !output.status.success() {
+        bail!("'{cmd}' failed: {}", output.status);
+    }
+    read_entries(output.stdout.as_slice(), &format!("output of '{cmd}'"))
+}
+
+fn parse_entry(line: &str) -> Result<Entry> {
+    let mut fields = line.splitn(3, '\t');
+    let key = fields.next().unwrap_or_default();
+    let Some(desc) = fields.next() else {
+        bail!("expected 'key<TAB>description[<TAB>command]'");
+    };
+    let cmd = fields.next().unwrap_or(desc);
+    let key: Key = SingleKey::from_str(key).map_err(Error::msg)?.into();
+    Ok(Entry::Cmd {
+        key,
+        cmd: vec![cmd.into()],
+        desc: desc.into(),
+        keep_open: None,
+        keep_going: false,
+        confirm: None,
+        show_output: false,
+        repeat: false,
+        color: None,
+        icon: None,
+        font: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Description and command of the entries read from `input`.
+    fn read(input: &str) -> Result<Vec<(String, String)>> {
+        let entries = read_entries(input.as_bytes(), "stdin")?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| match entry {
+                Entry::Cmd { desc, mut cmd, .. } => (desc, cmd.remove(0)),
+                _ => unreachable!(),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn missing_cmd() {
+        assert_eq!(
+            read("a\tFirefox\n\nb\tFiles\tnautilus\n").unwrap(),
+            [
+                ("Firefox".to_owned(), "Firefox".to_owned()),
+                ("Files".to_owned(), "nautilus".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_tabs() {
+        assert_eq!(
+            read("a\tTabs\tprintf 'a\tb'\n").unwrap(),
+            [("Tabs".to_owned(), "printf 'a\tb'".to_owned())]
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let err = read("a\tA\nnot-a-key\tB\n").unwrap_err();
+        assert_eq!(format!("{err:#}"), "stdin line 2: invalid key 'not-a-key'");
+        let err = read("a\n").unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "stdin line 1: expected 'key<TAB>description[<TAB>command]'"
+        );
+    }
+}
@@ -1,12 +1,16 @@
 use std::fmt;
+use std::ops::Range;
 use std::str::FromStr;
 
-use serde::de;
+use serde::{de, ser};
 use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers};
+use xkbcommon::xkb;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Key {
     any_of: Vec<SingleKey>,
+    /// Match whatever other modifiers are held besides those of the key.
+    ignore_extra_modifiers: bool,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
@@ -14,16 +18,96 @@ pub struct ModifierState {
     pub mod_ctrl: bool,
     pub mod_alt: bool,
     pub mod_mod4: bool,
+    /// Hyper. Not reported by sctk, so only known from the Hyper keys pressed, see `ModifierSides`.
+    pub mod_mod3: bool,
+    /// ISO_Level3_Shift, which is AltGr on many layouts. Not reported by sctk either.
+    pub mod_mod5: bool,
+    /// The modifier keys held, or required by a key binding.
+    pub sides: ModifierSides,
+}
+
+/// Left and right modifier keys, which the compositor only reports combined.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ModifierSides {
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub left_mod4: bool,
+    pub right_mod4: bool,
+    /// Either Hyper key, for `ModifierState::mod_mod3`.
+    pub hyper: bool,
+    /// ISO_Level3_Shift, for `ModifierState::mod_mod5`.
+    pub level3: bool,
 }
 
 impl ModifierState {
-    pub fn from_sctk_modifiers(mods: &Modifiers) -> Self {
+    /// The modifiers from the compositor, with `sides` tracked from the key presses.
+    pub fn from_sctk_modifiers(mods: &Modifiers, sides: ModifierSides) -> Self {
         Self {
             mod_ctrl: mods.ctrl,
             mod_alt: mods.alt,
             mod_mod4: mods.logo,
+            mod_mod3: sides.hyper,
+            mod_mod5: sides.level3,
+            sides,
         }
     }
+
+    /// Record a press or release of `keysym`, if it is a modifier key. Hyper and Level3 are
+    /// only known this way.
+    pub fn update_key(&mut self, keysym: Keysym, pressed: bool) {
+        self.sides.update(keysym, pressed);
+        self.mod_mod3 = self.sides.hyper;
+        self.mod_mod5 = self.sides.level3;
+    }
+
+    /// Whether these modifiers, held during a key press, trigger a binding with `required`.
+    pub fn satisfies(&self, required: &Self) -> bool {
+        self.mod_ctrl == required.mod_ctrl
+            && self.mod_alt == required.mod_alt
+            && self.mod_mod4 == required.mod_mod4
+            && self.mod_mod3 == required.mod_mod3
+            && self.mod_mod5 == required.mod_mod5
+            && self.sides.contains(&required.sides)
+    }
+
+    /// Like `satisfies`, but other modifiers may be held as well.
+    pub fn includes(&self, required: &Self) -> bool {
+        (self.mod_ctrl || !required.mod_ctrl)
+            && (self.mod_alt || !required.mod_alt)
+            && (self.mod_mod4 || !required.mod_mod4)
+            && (self.mod_mod3 || !required.mod_mod3)
+            && (self.mod_mod5 || !required.mod_mod5)
+            && self.sides.contains(&required.sides)
+    }
+}
+
+impl ModifierSides {
+    /// Record a press or release of `keysym`, if it is a modifier key.
+    pub fn update(&mut self, keysym: Keysym, pressed: bool) {
+        let side = match keysym {
+            Keysym::Control_L => &mut self.left_ctrl,
+            Keysym::Control_R => &mut self.right_ctrl,
+            Keysym::Alt_L | Keysym::Meta_L => &mut self.left_alt,
+            Keysym::Alt_R | Keysym::Meta_R => &mut self.right_alt,
+            Keysym::Super_L => &mut self.left_mod4,
+            Keysym::Super_R => &mut self.right_mod4,
+            Keysym::Hyper_L | Keysym::Hyper_R => &mut self.hyper,
+            Keysym::ISO_Level3_Shift => &mut self.level3,
+            _ => return,
+        };
+        *side = pressed;
+    }
+
+    fn contains(&self, other: &Self) -> bool {
+        (self.left_ctrl || !other.left_ctrl)
+            && (self.right_ctrl || !other.right_ctrl)
+            && (self.left_alt || !other.left_alt)
+            && (self.right_alt || !other.right_alt)
+            && (self.left_mod4 || !other.left_mod4)
+            && (self.right_mod4 || !other.right_mod4)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -34,19 +118,90 @@ pub struct SingleKey {
 }
 
 impl Key {
+    /// The representation of the first alternative, used where a single key name is expected.
+    pub fn primary_repr(&self) -> &str {
+        self.any_of.first().map_or("", |key| &key.repr)
+    }
+
+    pub fn alternatives(&self) -> &[SingleKey] {
+        &self.any_of
+    }
+
+    /// Byte ranges of the modifiers, like `Ctrl+` in `Ctrl+x`, in the text shown for this key.
+    pub fn modifier_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        for key in &self.any_of {
+            let len = key.modifiers_len();
+            if len > 0 {
+                ranges.push(start..start + len);
+            }
+            start += key.repr.len() + ALTERNATIVES_SEPARATOR.len();
+        }
+        ranges
+    }
+
+    /// An empty list of keys, which matches nothing. Left to `auto_keys` in entries.
+    pub fn is_empty(&self) -> bool {
+        self.any_of.is_empty()
+    }
+
+    /// This key with each keysym replaced by the result of `translate`, keeping the modifiers.
+    /// Keysyms for which `translate` returns `None` are kept as well.
+    pub fn translated(&self, translate: impl Fn(Keysym) -> Option<Keysym>) -> Self {
+        let any_of = self
+            .any_of
+            .iter()
+            .map(|key| match translate(key.keysym) {
+                Some(keysym) => key.with_keysym(keysym),
+                None => key.clone(),
+            })
+            .collect();
+        Self {
+            any_of,
+            ignore_extra_modifiers: self.ignore_extra_modifiers,
+        }
+    }
+
+    /// Whether exactly the modifiers of the key must be held, which is the default.
+    pub fn exact_modifiers(&self) -> bool {
+        !self.ignore_extra_modifiers
+    }
+
+    pub fn set_exact_modifiers(&mut self, exact: bool) {
+        self.ignore_extra_modifiers = !exact;
+    }
+
     pub fn matches(&self, sym: Keysym, modifiers: ModifierState) -> bool {
         self.any_of
             .iter()
-            .any(|key| key.modifiers == modifiers && key.keysym == sym)
+            .any(|key| self.matches_single(key, sym, modifiers))
+    }
+
+    /// Like `matches`, but ignoring the wildcard.
+    pub fn matches_specific(&self, sym: Keysym, modifiers: ModifierState) -> bool {
+        self.any_of
+            .iter()
+            .any(|key| !key.is_wildcard() && self.matches_single(key, sym, modifiers))
+    }
+
+    fn matches_single(&self, key: &SingleKey, sym: Keysym, modifiers: ModifierState) -> bool {
+        match self.ignore_extra_modifiers {
+            true => key.matches_ignoring_extra(sym, modifiers),
+            false => key.matches(sym, modifiers),
+        }
     }
 }
 
+/// Shown between the alternatives of a key.
+const ALTERNATIVES_SEPARATOR: &str = " | ";
+
 impl fmt::Display for Key {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for (i, key) in self.any_of.iter().enumerate() {
             f.write_str(&key.repr)?;
             if i + 1 != self.any_of.len() {
-                f.write_str(" | ")?
+                f.write_str(ALTERNATIVES_SEPARATOR)?
             }
         }
         Ok(())
@@ -57,6 +212,78 @@ impl From<SingleKey> for Key {
     fn from(value: SingleKey) -> Self {
         Self {
             any_of: vec![value],
+            ignore_extra_modifiers: false,
+        }
+    }
+}
+
+impl SingleKey {
+    /// Whether every key press triggering `other` triggers this key as well, so that `other` is
+    /// never used when bound after it. `exact` and `other_exact` tell whether the keys match only
+    /// their exact modifiers, see `Key::exact_modifiers`.
+    ///
+    /// The wildcard only gets the presses no other key matches, so it only catches another
+    /// wildcard.
+    pub fn catches_all_of(&self, exact: bool, other: &Self, other_exact: bool) -> bool {
+        if self.is_wildcard() || other.is_wildcard() {
+            return self.is_wildcard() && other.is_wildcard();
+        }
+        self.keysym == other.keysym
+            && match (exact, other_exact) {
+                (true, true) => other.modifiers.satisfies(&self.modifiers),
+                // Presses of `other` with extra modifiers do not match this key.
+                (true, false) => false,
+                (false, _) => other.modifiers.includes(&self.modifiers),
+            }
+    }
+
+    /// The wildcard matches every key press but those of modifier keys, whatever the modifiers.
+    pub fn matches(&self, sym: Keysym, modifiers: ModifierState) -> bool {
+        match self.is_wildcard() {
+            true => !sym.is_modifier_key(),
+            false => modifiers.satisfies(&self.modifiers) && self.keysym == sym,
+        }
+    }
+
+    /// Like `matches`, but other modifiers than those of this key may be held as well.
+    pub fn matches_ignoring_extra(&self, sym: Keysym, modifiers: ModifierState) -> bool {
+        match self.is_wildcard() {
+            true => !sym.is_modifier_key(),
+            false => modifiers.includes(&self.modifiers) && self.keysym == sym,
+        }
+    }
+
+    /// Length of the modifiers at the start of `repr`, including the last `+`.
+    fn modifiers_len(&self) -> usize {
+        // The key itself may be `+`, without modifiers.
+        let Some(before_key) = self.repr.get(..self.repr.len().saturating_sub(1)) else {
+            return 0;
+        };
+        before_key.rfind('+').map_or(0, |i| i + 1)
+    }
+
+    /// Whether this is the `*` key, which matches any key press.
+    pub fn is_wildcard(&self) -> bool {
+        self.keysym == Keysym::NoSymbol
+    }
+
+    /// This key with a different keysym and the same modifiers.
+    fn with_keysym(&self, keysym: Keysym) -> Self {
+        let name = match keysym.key_char() {
+            Some(c) if !c.is_control() && !c.is_whitespace() => c.to_string(),
+            _ => keysym.name().map_or_else(
+                || format!("{:#x}", keysym.raw()),
+                |name| name.trim_start_matches("XK_").to_owned(),
+            ),
+        };
+        let repr = match self.repr.rsplit_once('+') {
+            Some((modifiers, _)) if !modifiers.is_empty() => format!("{modifiers}+{name}"),
+            _ => name,
+        };
+        Self {
+            keysym,
+            repr,
+            modifiers: self.modifiers,
         }
     }
 }
@@ -72,6 +299,13 @@ impl FromStr for SingleKey {
                 modifiers: Default::default(),
             });
         }
+        if s == "*" {
+            return Ok(Self {
+                keysym: Keysym::NoSymbol,
+                repr: String::from("*"),
+                modifiers: Default::default(),
+            });
+        }
 
         let mut components = s.split('+');
         let key = components.next_back().unwrap_or(s);
@@ -79,15 +313,49 @@ impl FromStr for SingleKey {
 
         let mut modifiers = ModifierState::default();
         for modifier in components {
-            if modifier.eq_ignore_ascii_case("ctrl") {
-                modifiers.mod_ctrl = true;
-            } else if modifier.eq_ignore_ascii_case("alt") {
-                modifiers.mod_alt = true;
-            } else if modifier.eq_ignore_ascii_case("mod4") || modifier.eq_ignore_ascii_case("logo")
-            {
-                modifiers.mod_mod4 = true;
-            } else {
-                return Err(format!("unknown modifier '{modifier}"));
+            let lower = modifier.to_ascii_lowercase();
+            // An `l` or `r` prefix only matches the key on that side.
+            let (name, side) = match lower.as_str() {
+                "lctrl" | "lalt" | "lmod4" | "llogo" => (&lower[1..], Some(false)),
+                "rctrl" | "ralt" | "rmod4" | "rlogo" => (&lower[1..], Some(true)),
+                name => (name, None),
+            };
+            // These have no sides, see `ModifierSides`.
+            match name {
+                "mod3" | "hyper" => {
+                    modifiers.mod_mod3 = true;
+                    continue;
+                }
+                "mod5" | "level3" => {
+                    modifiers.mod_mod5 = true;
+                    continue;
+                }
+                _ => (),
+            }
+            let sides = &mut modifiers.sides;
+            let (held, left, right) = match name {
+                "ctrl" => (
+                    &mut modifiers.mod_ctrl,
+                    &mut sides.left_ctrl,
+                    &mut sides.right_ctrl,
+                ),
+                "alt" => (
+                    &mut modifiers.mod_alt,
+                    &mut sides.left_alt,
+                    &mut sides.right_alt,
+                ),
+                "mod4" | "logo" => (
+                    &mut modifiers.mod_mod4,
+                    &mut sides.left_mod4,
+                    &mut sides.right_mod4,
+                ),
+                _ => return Err(format!("unknown modifier '{modifier}'")),
+            };
+            *held = true;
+            match side {
+                Some(false) => *left = true,
+                Some(true) => *right = true,
+                None => (),
             }
         }
 
@@ -103,35 +371,14 @@ fn to_keysym(s: &str) -> Option<Keysym> {
     let mut chars = s.chars();
     let first_char = chars.next()?;
 
-    let keysym = if chars.next().is_none() {
+    let keysym = if chars.all(is_presentation_modifier) {
         Keysym::from_char(first_char)
     } else {
-        match &*s.to_ascii_uppercase() {
-            "F1" => Keysym::F1,
-            "F2" => Keysym::F2,
-            "F3" => Keysym::F3,
-            "F4" => Keysym::F4,
-            "F5" => Keysym::F5,
-            "F6" => Keysym::F6,
-            "F7" => Keysym::F7,
-            "F8" => Keysym::F8,
-            "F9" => Keysym::F9,
-            "F10" => Keysym::F10,
-            "F11" => Keysym::F11,
-            "F12" => Keysym::F12,
-            "F13" => Keysym::F13,
-            "F14" => Keysym::F14,
-            "F15" => Keysym::F15,
-            "F16" => Keysym::F16,
-            "F17" => Keysym::F17,
-            "F18" => Keysym::F18,
-            "F19" => Keysym::F19,
-            "F20" => Keysym::F20,
-            "F21" => Keysym::F21,
-            "F22" => Keysym::F22,
-            "F23" => Keysym::F23,
-            "F24" => Keysym::F24,
-            _ => Keysym::NoSymbol,
+        // Any xkb keysym name, like `Return` or `XF86AudioMute`. An exact match is preferred, as
+        // some names only differ in case.
+        match xkb::keysym_from_name(s, xkb::KEYSYM_NO_FLAGS) {
+            Keysym::NoSymbol => xkb::keysym_from_name(s, xkb::KEYSYM_CASE_INSENSITIVE),
+            keysym => keysym,
         }
     };
 
@@ -142,6 +389,13 @@ fn to_keysym(s: &str) -> Option<Keysym> {
     }
 }
 
+/// Characters which only change how the character before them is drawn, like the emoji
+/// presentation selector in "❤️" or skin tones. They are kept in the label but do not change the
+/// key.
+fn is_presentation_modifier(c: char) -> bool {
+    matches!(c, '\u{FE0E}' | '\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}')
+}
+
 impl<'de> de::Deserialize<'de> for Key {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -171,7 +425,10 @@ impl<'de> de::Deserialize<'de> for Key {
                 while let Some(next) = seq.next_element()? {
                     any_of.push(next);
                 }
-                Ok(Key { any_of })
+                Ok(Key {
+                    any_of,
+                    ignore_extra_modifiers: false,
+                })
             }
         }
 
@@ -179,6 +436,27 @@ impl<'de> de::Deserialize<'de> for Key {
     }
 }
 
+impl ser::Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        match self.any_of.as_slice() {
+            [key] => key.serialize(serializer),
+            keys => serializer.collect_seq(keys),
+        }
+    }
+}
+
+impl ser::Serialize for SingleKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.serialize_str(&self.repr)
+    }
+}
+
 impl<'de> de::Deserialize<'de> for SingleKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -204,3 +482,109 @@ impl<'de> de::Deserialize<'de> for SingleKey {
         deserializer.deserialize_str(KeyVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emoji_keys() {
+        let heart = Some(Keysym::from_char('❤'));
+        assert_eq!(to_keysym("❤"), heart);
+        assert_eq!(to_keysym("❤️"), heart);
+        assert_eq!(to_keysym("👍🏽"), Some(Keysym::from_char('👍')));
+        assert_eq!(to_keysym("ab"), None);
+
+        let key: SingleKey = "Ctrl+❤️".parse().unwrap();
+        assert_eq!(key.keysym, Keysym::from_char('❤'));
+        assert_eq!(key.repr, "Ctrl+❤️");
+    }
+
+    #[test]
+    fn wildcard_key() {
+        let key: SingleKey = "*".parse().unwrap();
+        assert!(key.is_wildcard());
+        assert!(key.matches(Keysym::a, ModifierState::default()));
+        assert!(!key.matches(Keysym::Control_L, ModifierState::default()));
+        let asterisk: SingleKey = "asterisk".parse().unwrap();
+        assert!(!asterisk.is_wildcard());
+        assert!(!key.catches_all_of(true, &asterisk, true));
+        assert!(!asterisk.catches_all_of(true, &key, true));
+        assert!(key.catches_all_of(true, &key, true));
+    }
+
+    #[test]
+    fn hyper_and_level3() {
+        let hyper: SingleKey = "Hyper+a".parse().unwrap();
+        assert!(hyper.modifiers.mod_mod3 && !hyper.modifiers.mod_mod5);
+        let level3: SingleKey = "mod5+a".parse().unwrap();
+        assert!(level3.modifiers.mod_mod5 && !level3.modifiers.mod_mod3);
+
+        let mut held = ModifierState::default();
+        assert!(!hyper.matches(Keysym::a, held));
+        held.update_key(Keysym::Hyper_R, true);
+        assert!(hyper.matches(Keysym::a, held));
+        assert!(!level3.matches(Keysym::a, held));
+        held.update_key(Keysym::Hyper_R, false);
+        held.update_key(Keysym::ISO_Level3_Shift, true);
+        assert!(level3.matches(Keysym::a, held));
+        assert!(!"a".parse::<SingleKey>().unwrap().matches(Keysym::a, held));
+    }
+
+    #[test]
+    fn modifier_ranges() {
+        let key: Key = serde_yaml::from_str("[Ctrl+Alt+x, y, Alt+z]").unwrap();
+        assert_eq!(key.to_string(), "Ctrl+Alt+x | y | Alt+z");
+        assert_eq!(key.modifier_ranges(), [0..9, 17..21]);
+        let plus: Key = serde_yaml::from_str("\"+\"").unwrap();
+        assert!(plus.modifier_ranges().is_empty());
+    }
+
+    #[test]
+    fn exact_modifiers() {
+        let mut key: Key = serde_yaml::from_str("Ctrl+a").unwrap();
+        let ctrl = ModifierState {
+            mod_ctrl: true,
+            ..Default::default()
+        };
+        let ctrl_alt = ModifierState {
+            mod_alt: true,
+            ..ctrl
+        };
+        assert!(key.exact_modifiers());
+        assert!(key.matches(Keysym::a, ctrl));
+        assert!(!key.matches(Keysym::a, ctrl_alt));
+
+        key.set_exact_modifiers(false);
+        assert!(key.matches(Keysym::a, ctrl));
+        assert!(key.matches(Keysym::a, ctrl_alt));
+        // The modifiers of the key are still required.
+        assert!(!key.matches(Keysym::a, ModifierState::default()));
+        assert!(!key.translated(|_| None).exact_modifiers());
+    }
+
+    #[test]
+    fn keysym_names() {
+        assert_eq!(to_keysym("F13"), Some(Keysym::F13));
+        assert_eq!(to_keysym("XF86AudioMute"), Some(Keysym::XF86_AudioMute));
+        assert_eq!(to_keysym("Escape"), Some(Keysym::Escape));
+        assert_eq!(to_keysym("bracketleft"), Some(Keysym::bracketleft));
+        assert_eq!(to_keysym("asterisk"), Some(Keysym::asterisk));
+    }
+
+    #[test]
+    fn keysym_names_ignore_case() {
+        assert_eq!(to_keysym("return"), Some(Keysym::Return));
+        assert_eq!(to_keysym("xf86audiomute"), Some(Keysym::XF86_AudioMute));
+        // Names which only differ in case keep their exact match.
+        assert_eq!(to_keysym("eacute"), Some(Keysym::eacute));
+        assert_eq!(to_keysym("Eacute"), Some(Keysym::Eacute));
+    }
+
+    #[test]
+    fn unknown_keysym_name() {
+        assert_eq!(to_keysym("NotAKey"), None);
+        assert_eq!(to_keysym(""), None);
+        assert!("Ctrl+NotAKey".parse::<SingleKey>().is_err());
+    }
+}
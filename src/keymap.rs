@@ -0,0 +1,88 @@
+use smithay_client_toolkit::seat::keyboard::Keysym;
+use xkbcommon::xkb;
+
+/// The keymap of the keyboard, used to find which symbol a key produces in each layout.
+pub struct Keymap {
+    keymap: xkb::Keymap,
+}
+
+impl Keymap {
+    /// Compile a keymap in the text format sent by the compositor.
+    pub fn from_string(keymap: String) -> Option<Self> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymap,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )?;
+        Some(Self { keymap })
+    }
+
+    /// The symbol produced in `layout` by the key that produces `keysym` in the first layout
+    /// which has it, at the same shift level.
+    ///
+    /// Returns `None` if `keysym` is already produced by some key in `layout`, if no layout has
+    /// it, or if `layout` has nothing on that key.
+    pub fn translate(&self, keysym: Keysym, layout: u32) -> Option<Keysym> {
+        if self.position(keysym, Some(layout)).is_some() {
+            return None;
+        }
+        let (keycode, level) = self.position(keysym, None)?;
+        if layout >= self.keymap.num_layouts_for_key(keycode)
+            || level >= self.keymap.num_levels_for_key(keycode, layout)
+        {
+            return None;
+        }
+        self.keymap
+            .key_get_syms_by_level(keycode, layout, level)
+            .first()
+            .copied()
+    }
+
+    /// The key code and shift level producing `keysym`, in `layout` or in any layout if `None`.
+    /// Earlier layouts are preferred.
+    fn position(&self, keysym: Keysym, layout: Option<u32>) -> Option<(xkb::Keycode, u32)> {
+        let layouts = match layout {
+            Some(layout) => layout..layout + 1,
+            None => 0..self.keymap.num_layouts(),
+        };
+        let keycodes = self.keymap.min_keycode().raw()..=self.keymap.max_keycode().raw();
+        for layout in layouts {
+            for keycode in keycodes.clone().map(xkb::Keycode::new) {
+                if layout >= self.keymap.num_layouts_for_key(keycode) {
+                    continue;
+                }
+                for level in 0..self.keymap.num_levels_for_key(keycode, layout) {
+                    let syms = self.keymap.key_get_syms_by_level(keycode, layout, level);
+                    if syms.contains(&keysym) {
+                        return Some((keycode, level));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(layouts: &str) -> Keymap {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap =
+            xkb::Keymap::new_from_names(&context, "", "pc105", layouts, "", None, 0).unwrap();
+        Keymap::from_string(keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1)).unwrap()
+    }
+
+    #[test]
+    fn translate_to_other_layout() {
+        let keymap = keymap("us,ru");
+        assert_eq!(keymap.translate(Keysym::a, 1), Some(Keysym::Cyrillic_ef));
+        assert_eq!(keymap.translate(Keysym::A, 1), Some(Keysym::Cyrillic_EF));
+        // Already on the first layout, and keys without letters are shared.
+        assert_eq!(keymap.translate(Keysym::a, 0), None);
+        assert_eq!(keymap.translate(Keysym::Escape, 1), None);
+    }
+}
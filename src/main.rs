@@ -1,37 +1,67 @@
 mod color;
 mod config;
+mod dmenu;
 mod key;
+mod keymap;
 mod menu;
+mod outputs;
+mod signal;
 mod text;
+mod watch;
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::f64::consts::{FRAC_PI_2, PI, TAU};
-use std::io;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
 use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::LazyLock;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use anyhow::bail;
+use anyhow::{Context, bail};
 use clap::Parser;
 use pangocairo::cairo;
 use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState};
+use smithay_client_toolkit::data_device_manager::data_device::{DataDevice, DataDeviceHandler};
+use smithay_client_toolkit::data_device_manager::data_offer::{DataOfferHandler, DragOffer};
+use smithay_client_toolkit::data_device_manager::data_source::{CopyPasteSource, DataSourceHandler};
+use smithay_client_toolkit::data_device_manager::{DataDeviceManagerState, WritePipe};
+use smithay_client_toolkit::reexports::calloop::channel::{self, Sender};
+use smithay_client_toolkit::reexports::calloop::generic::Generic;
+use smithay_client_toolkit::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay_client_toolkit::reexports::calloop::{EventLoop, Interest, LoopHandle, Mode, PostAction, RegistrationToken};
+use smithay_client_toolkit::reexports::calloop_wayland_source::WaylandSource;
 use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
 use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1::ZwpKeyboardShortcutsInhibitorV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::{self, WpFractionalScaleV1};
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::reexports::protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::{Shape, WpCursorShapeDeviceV1};
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
 use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
-use smithay_client_toolkit::{delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_registry, delegate_seat, delegate_shm};
+use smithay_client_toolkit::{delegate_compositor, delegate_data_device, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer, delegate_registry, delegate_seat, delegate_shm};
 use smithay_client_toolkit::output::{OutputHandler, OutputState};
-use smithay_client_toolkit::seat::{keyboard::KeyboardHandler, Capability, SeatHandler, SeatState};
-use smithay_client_toolkit::shell::wlr_layer::{KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface};
+use smithay_client_toolkit::seat::{keyboard::{KeyEvent, KeyboardHandler}, Capability, SeatHandler, SeatState};
+use smithay_client_toolkit::seat::pointer::{PointerEvent, PointerEventKind, PointerHandler, BTN_LEFT};
+use smithay_client_toolkit::seat::pointer::cursor_shape::CursorShapeManager;
+use smithay_client_toolkit::shell::wlr_layer::{KeyboardInteractivity, LayerShell, LayerShellHandler, LayerSurface};
 use smithay_client_toolkit::shell::WaylandSurface;
 use smithay_client_toolkit::shm::slot::SlotPool;
 use smithay_client_toolkit::shm::{Shm, ShmHandler};
 use wayland_client::globals::registry_queue_init;
+use wayland_client::protocol::wl_data_device::WlDataDevice;
+use wayland_client::protocol::wl_data_device_manager::DndAction;
+use wayland_client::protocol::wl_data_source::WlDataSource;
 use wayland_client::protocol::wl_keyboard::WlKeyboard;
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::protocol::wl_pointer::WlPointer;
 use wayland_client::protocol::wl_seat::WlSeat;
 use wayland_client::protocol::wl_shm::Format;
 use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
 
-use crate::key::ModifierState;
+use crate::key::{ModifierSides, ModifierState};
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -42,36 +72,285 @@ struct Args {
     /// ~/.config/wlr-which-key/config.yaml is used.
     ///
     /// For example, to use ~/.config/wlr-which-key/print-srceen.yaml, set this to
-    /// "print-srceen". An absolute path can be used too, extension is optional.
+    /// "print-srceen". An absolute path can be used too, extension is optional. Without one,
+    /// .yaml, .yml, .json and .toml files are tried in this order.
+    ///
+    /// Use "-" to read a YAML config from stdin.
     config: Option<String>,
 
+    /// Use this directory instead of $XDG_CONFIG_HOME or ~/.config.
+    ///
+    /// Config names and includes are then looked up in <PATH>/wlr-which-key.
+    #[arg(long, value_name = "PATH")]
+    config_dir: Option<PathBuf>,
+
     /// Initial key sequence to navigate to a specific submenu on startup.
     ///
     /// Provide a sequence of keys separated by spaces to navigate directly to a submenu.
-    /// For example, "p s" would navigate to the submenu at key 'p', then 's'.
+    /// For example, "p s" would navigate to the submenu at key 'p', then 's'. Keys may include
+    /// modifiers like in the config, e.g. "Ctrl+a s".
+    /// If the sequence ends at a command, the command is executed. Commands with keep_open=true
+    /// then show the menu they are in.
     /// The application will show an error and exit if the key sequence is invalid.
     #[arg(long, short = 'k')]
     initial_keys: Option<String>,
+
+    /// Exit with an error if --initial-keys leads to a submenu without entries.
+    ///
+    /// Helps scripts notice key sequences which no longer match the config.
+    #[arg(long, requires = "initial_keys")]
+    strict_initial_keys: bool,
+
+    /// Report the selected command to a Unix socket instead of executing it.
+    ///
+    /// The selection is written as a single JSON line with the "path" (keys pressed), "cmd" and
+    /// "desc" of the entry. A new connection is made for every selection.
+    #[arg(long, value_name = "PATH")]
+    emit_socket: Option<PathBuf>,
+
+    /// Read the menu from stdin instead of the config file.
+    ///
+    /// Every line is an entry of the form "key<TAB>description<TAB>command". The command column
+    /// is optional and defaults to the description. The config file, if it exists, is still
+    /// used for everything but the menu.
+    #[arg(long)]
+    from_stdin: bool,
+
+    /// Keep running when the menu closes, and show it again on SIGUSR1.
+    ///
+    /// The menu is shown from the top-level menu, or from --initial-keys if given. This avoids
+    /// starting a new process and parsing the config each time, so the menu appears faster.
+    #[arg(long)]
+    daemon: bool,
+
+    /// Print the command of the selected entry to stdout instead of executing it.
+    ///
+    /// Commands with keep_open=true leave the menu open, so that several can be printed, one per
+    /// line.
+    #[arg(long, visible_alias = "print-command")]
+    print_selection: bool,
+
+    /// Print an outline of the menu with includes resolved and exit.
+    #[arg(long)]
+    print_tree: bool,
+
+    /// Check the config for mistakes and exit. Exits with an error if any are found.
+    ///
+    /// Besides parse errors, this reports duplicate keys within a menu, entries which can never
+    /// be selected and empty descriptions.
+    #[arg(long)]
+    validate: bool,
+
+    /// Print the default config with all options as YAML and exit.
+    #[arg(long)]
+    dump_default_config: bool,
+
+    /// Print the name, current mode, scale and description of each output and exit.
+    ///
+    /// The name is what the `output` config option expects.
+    #[arg(long)]
+    list_outputs: bool,
+
+    /// Give the menu this size, and place its top-left corner at X, Y from the top-left corner of
+    /// the output if given, whatever the config says.
+    #[arg(long, value_name = "WxH[+X+Y]")]
+    geometry: Option<Geometry>,
+
+    /// Whether to inhibit the keyboard shortcuts of the compositor, whatever
+    /// inhibit_compositor_keyboard_shortcuts says in the config.
+    #[arg(long, value_name = "BOOL")]
+    inhibit_shortcuts: Option<bool>,
+
+    /// Outline the parts of the menu, to troubleshoot its layout.
+    ///
+    /// Texts are outlined in red, columns in blue, the area of each entry in green and the
+    /// padding and border in magenta. Also enabled with WLR_WHICH_KEY_LAYOUT_DEBUG=1.
+    #[arg(long)]
+    debug_layout: bool,
+}
+
+/// Size and position of the menu given with `--geometry`, in the form `WxH[+X+Y]`.
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    width: u32,
+    height: u32,
+    position: Option<(i32, i32)>,
+}
+
+impl Geometry {
+    /// Replace the size of the menu and its position, if given, set in `config`.
+    fn apply(&self, config: &mut config::Config, menu: &mut menu::Menu) {
+        config.width_fraction = None;
+        config.height_fraction = None;
+        if let Some((x, y)) = self.position {
+            config.anchor = config::ConfigAnchor::TopLeft;
+            config.margin_left = x;
+            config.margin_top = y;
+            config.offset_x = 0;
+            config.offset_y = 0;
+        }
+        menu.set_fixed_size((Some(self.width as f64), Some(self.height as f64)));
+    }
+}
+
+impl FromStr for Geometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid geometry '{s}', expected WIDTHxHEIGHT[+X+Y]");
+        let (size, position) = match s.split_once('+') {
+            Some((size, position)) => (size, Some(position)),
+            None => (s, None),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let width = width.parse().map_err(|_| invalid())?;
+        let height = height.parse().map_err(|_| invalid())?;
+        let position = match position.map(|p| p.split_once('+')) {
+            Some(Some((x, y))) => Some((
+                x.parse().map_err(|_| invalid())?,
+                y.parse().map_err(|_| invalid())?,
+            )),
+            Some(None) => return Err(invalid()),
+            None => None,
+        };
+        Ok(Self {
+            width,
+            height,
+            position,
+        })
+    }
+}
+
+thread_local! {
+    /// Set by `--debug-layout` or `WLR_WHICH_KEY_LAYOUT_DEBUG=1`. The menu is only drawn on the
+    /// main thread, and tests can set it without affecting each other.
+    static DEBUG_LAYOUT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether to outline the texts, columns, entries and padding of the menu.
+fn debug_layout() -> bool {
+    DEBUG_LAYOUT.get()
 }
 
-static DEBUG_LAYOUT: LazyLock<bool> =
-    LazyLock::new(|| std::env::var("WLR_WHICH_KEY_LAYOUT_DEBUG").as_deref() == Ok("1"));
+/// Whether `--debug-layout` or the `WLR_WHICH_KEY_LAYOUT_DEBUG` variable asks for
+/// `debug_layout`.
+fn debug_layout_requested(flag: bool, env: Option<&str>) -> bool {
+    flag || env == Some("1")
+}
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    let config = config::Config::new(args.config.as_deref().unwrap_or("config"))?;
+    DEBUG_LAYOUT.set(debug_layout_requested(
+        args.debug_layout,
+        std::env::var("WLR_WHICH_KEY_LAYOUT_DEBUG").ok().as_deref(),
+    ));
+    if args.dump_default_config {
+        let yaml = serde_yaml::to_string(&config::Config::default())
+            .context("Failed to serialize the default config")?;
+        print!("{yaml}");
+        return Ok(());
+    }
+    if args.list_outputs {
+        return outputs::list();
+    }
+    if let Some(dir) = &args.config_dir {
+        config::set_config_dir(dir.clone());
+    }
+    let config_name = args.config.as_deref().unwrap_or("config");
+    let config_from_stdin = config_name == "-";
+    let mut config = if config_from_stdin {
+        if args.from_stdin {
+            bail!("--from-stdin cannot be used with a config read from stdin");
+        }
+        config::Config::from_stdin()?
+    } else if args.from_stdin {
+        let mut config = config::Config::new_or_default(config_name)?;
+        config.menu = dmenu::read_entries(io::stdin().lock(), "stdin")?;
+        config
+    } else {
+        config::Config::new(config_name)?
+    };
+
+    if args.print_tree {
+        print_tree(
+            &mut io::stdout().lock(),
+            &config.menu,
+            config.keep_open_default,
+            0,
+        )?;
+        return Ok(());
+    }
     let mut menu = menu::Menu::new(&config)?;
+    if let Some(geometry) = &args.geometry {
+        geometry.apply(&mut config, &mut menu);
+    }
+    if let Some(inhibit) = args.inhibit_shortcuts {
+        config.inhibit_compositor_keyboard_shortcuts = inhibit;
+    }
+
+    if args.validate {
+        let problems = config.problems();
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        if !problems.is_empty() {
+            bail!("{} problem(s) found in the config", problems.len());
+        }
+        return Ok(());
+    }
+    config.warn_duplicate_keys();
 
-    if let Some(initial_keys) = &args.initial_keys {
-        if let Some(initial_action) = menu.navigate_to_key_sequence(initial_keys)? {
-            match initial_action {
-                menu::Action::Submenu(_) => unreachable!(),
-                menu::Action::Quit => return Ok(()),
-                menu::Action::Exec { cmd, keep_open } => {
-                    if keep_open {
-                        bail!("Initial key sequence cannot trigger an action with keep_open=true");
+    // Dynamic entries run in the background, so that the menu does not wait for them to show up.
+    // Their output is handled once the event loop runs.
+    let (dynamic_sender, dynamic_channel) = channel::channel();
+    menu.set_dynamic_sender(dynamic_sender.clone());
+
+    let initial_action = match &args.initial_keys {
+        Some(initial_keys) => menu.navigate_to_key_sequence(initial_keys)?,
+        None => None,
+    };
+    if args.strict_initial_keys && initial_action.is_none() && !menu.has_entries() {
+        bail!("Initial key sequence leads to an empty submenu");
+    }
+    if let Some(initial_action) = initial_action {
+        match initial_action {
+            menu::Action::Submenu(_) => unreachable!(),
+            menu::Action::Confirm(page) => menu.set_page(page),
+            menu::Action::Search => menu.start_search(),
+            menu::Action::Scroll(_) => unreachable!(),
+            // The config has just been loaded, so only going back to the top-level menu is left.
+            menu::Action::Reload => menu.set_page(0),
+            menu::Action::Copy(_) => {
+                bail!("Initial key sequence cannot trigger a copy entry");
+            }
+            menu::Action::Quit => return Ok(()),
+            menu::Action::Exec {
+                cmd,
+                keep_open,
+                keep_going,
+                show_output,
+                desc,
+                path,
+                submenu,
+                ..
+            } => {
+                let cmd_line = chain_repr(&cmd, keep_going);
+                match &args.emit_socket {
+                    Some(socket) => emit(socket, &cmd_line, &desc, &path),
+                    None if args.print_selection => println!("{cmd_line}"),
+                    None if show_output => {
+                        let output = exec_capture(&config, &cmd, keep_going, &desc, &path)
+                            .with_context(|| format!("Failed to run '{cmd_line}'"))?;
+                        menu.set_status(&output);
                     }
-                    exec(&cmd);
+                    None => exec(&config, &cmd, keep_going, &desc, &path)
+                        .with_context(|| format!("Failed to run '{cmd_line}'"))?,
+                }
+                if let Some(page) = submenu {
+                    menu.set_page(page);
+                }
+                // Like a key press in the menu, keep_open commands show the menu they are in.
+                if !keep_open {
                     return Ok(());
                 }
             }
@@ -100,28 +379,41 @@ fn main() -> anyhow::Result<()> {
         false => None,
     };
 
+    // Both globals are needed to render at fractional scales, otherwise the integer
+    // wl_surface scale is used.
+    let fractional_scale_globals = globals
+        .bind(&qh, 1..=1, ())
+        .ok()
+        .zip(globals.bind(&qh, 1..=1, ()).ok());
+
     let shm = Shm::bind(&globals, &qh).expect("wl_shm is not available");
 
-    let width = menu.width(&config) as u32;
-    let height = menu.height(&config) as u32;
+    // Only needed for copy entries.
+    let data_device_manager = DataDeviceManagerState::bind(&globals, &qh).ok();
 
-    let surface = wl_compositor.create_surface(&qh);
+    // Only needed to show a hand over entries, the compositor picks the cursor otherwise.
+    let cursor_shape_manager = CursorShapeManager::bind(&globals, &qh).ok();
 
-    let layer_surface =
-        layer_shell.create_layer_surface(&qh, surface, Layer::Overlay, Some("wlr_which_key"), None);
-    layer_surface.set_anchor(config.anchor.into());
-    layer_surface.set_size(width, height);
-    layer_surface.set_margin(
-        config.margin_top,
-        config.margin_right,
-        config.margin_bottom,
-        config.margin_left,
+    let (width, height) = surface_size(&menu, &config);
+
+    let layer_surface = create_layer_surface(
+        &wl_compositor,
+        &layer_shell,
+        &qh,
+        &config,
+        menu.anchor().unwrap_or(config.anchor),
+        None,
+        (width, height),
     );
-    layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+    let surface_scaler = fractional_scale_globals
+        .as_ref()
+        .map(|globals| create_surface_scaler(globals, &layer_surface, &qh));
 
-    layer_surface.commit();
+    // Created before the state so that keyboards can repeat keys through it.
+    let mut event_loop = EventLoop::<State>::try_new()?;
 
     let mut state = State {
+        loop_handle: event_loop.handle(),
         pool: SlotPool::new((width * height * 4) as usize, &shm).unwrap(),
         keyboard_shortcuts_inhibit_manager,
         keyboard_shortcuts_inhibitors: HashMap::new(),
@@ -129,31 +421,134 @@ fn main() -> anyhow::Result<()> {
         shm,
         output,
         registry_state,
+        wl_compositor,
+        layer_shell,
         layer_surface,
+        layer_output: None,
         seat,
-        keyboard: None,
-
-        surface_scale: 1,
+        keyboards: HashMap::new(),
+        pointer: None,
+        cursor_shape_manager,
+        cursor_shape_device: None,
+        cursor_shape: None,
+        data_device_manager,
+        data_device: None,
+        copy_source: None,
+        last_serial: 0,
+
+        fractional_scale_globals,
+        surface_scaler,
+        scale: 1.0,
+        has_focus: false,
         exit: false,
         configured: false,
+        fade_start: config.fade_in_ms.map(|_| Instant::now()),
         width,
         height,
         damaged: true,
 
         menu,
         config,
+        emit_socket: args.emit_socket,
+        print_selection: args.print_selection,
+        visibility: Visibility {
+            daemon: args.daemon,
+            hidden: false,
+        },
+        initial_keys: args.initial_keys,
+        geometry: args.geometry,
+        preview: None,
+        preview_timer: None,
+        dynamic_sender,
 
         modifiers: ModifierState::default(),
+        last_press: None,
+        keymap: None,
+        kbd_layout: 0,
+    };
+
+    // Receive the names of outputs so that the configured one can be picked.
+    event_queue.roundtrip(&mut state)?;
+    if let Some(name) = &state.config.output
+        && state.layer_output.is_none()
+    {
+        eprintln!("Warning: output '{name}' not found, using the default output");
+    }
+
+    WaylandSource::new(conn, event_queue)
+        .insert(event_loop.handle())
+        .unwrap();
+
+    event_loop
+        .handle()
+        .insert_source(dynamic_channel, |event, _, state| {
+            if let channel::Event::Msg(output) = event
+                && state.menu.finish_dynamic(output)
+            {
+                // The new entries are shown with the keys from the config.
+                state.update_kbd_layout();
+                state.resize();
+            }
+        })
+        .unwrap();
+
+    // A menu read from stdin must not be replaced by the one in the config file.
+    let watcher = match args.from_stdin || config_from_stdin {
+        true => None,
+        false => Some(watch::FileWatcher::new(&state.config.path)),
     };
+    match watcher {
+        None => (),
+        Some(Ok(watcher)) => {
+            event_loop
+                .handle()
+                .insert_source(
+                    Generic::new(watcher, Interest::READ, Mode::Level),
+                    |_, watcher, state| {
+                        // Safety: the watcher is not dropped
+                        if unsafe { watcher.get_mut() }.changed()? {
+                            state.reload_config();
+                        }
+                        Ok(PostAction::Continue)
+                    },
+                )
+                .unwrap();
+        }
+        Some(Err(err)) => {
+            eprintln!("Warning: cannot watch the config file for changes: {err}")
+        }
+    }
+
+    if state.visibility.daemon {
+        let signal_fd =
+            signal::SignalFd::new(libc::SIGUSR1).context("Failed to listen for SIGUSR1")?;
+        let show_qh = qh.clone();
+        event_loop
+            .handle()
+            .insert_source(
+                Generic::new(signal_fd, Interest::READ, Mode::Level),
+                move |_, signal_fd, state| {
+                    // Safety: the signal fd is not dropped
+                    if unsafe { signal_fd.get_mut() }.received()? {
+                        state.show(&show_qh);
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .unwrap();
+    }
 
-    while !state.exit {
-        event_queue.blocking_dispatch(&mut state).unwrap();
+    // Copied text is only available while we are running, so keep running until something else
+    // is copied.
+    while !state.exit || state.copy_source.is_some() {
+        event_loop.dispatch(None, &mut state)?;
     }
 
     Ok(())
 }
 
 struct State {
+    loop_handle: LoopHandle<'static, State>,
     pool: SlotPool,
     keyboard_shortcuts_inhibit_manager: Option<ZwpKeyboardShortcutsInhibitManagerV1>,
     keyboard_shortcuts_inhibitors: HashMap<WlSeat, ZwpKeyboardShortcutsInhibitorV1>,
@@ -161,26 +556,86 @@ struct State {
     shm: Shm,
     output: OutputState,
     registry_state: RegistryState,
+    wl_compositor: CompositorState,
+    layer_shell: LayerShell,
     layer_surface: LayerSurface,
+    /// The output the layer surface was explicitly created on.
+    layer_output: Option<WlOutput>,
     seat: SeatState,
-    keyboard: Option<WlKeyboard>,
-
-    surface_scale: u32,
+    /// The keyboard of each seat. Keys from all of them are handled alike.
+    keyboards: HashMap<WlSeat, WlKeyboard>,
+    pointer: Option<WlPointer>,
+    cursor_shape_manager: Option<CursorShapeManager>,
+    cursor_shape_device: Option<WpCursorShapeDeviceV1>,
+    /// The shape last set and the serial of the pointer enter event it was set for.
+    cursor_shape: Option<(Shape, u32)>,
+    data_device_manager: Option<DataDeviceManagerState>,
+    data_device: Option<DataDevice>,
+    /// The clipboard contents we own, if any.
+    copy_source: Option<(CopyPasteSource, String)>,
+    /// Serial of the last key or button press, needed to set the selection.
+    last_serial: u32,
+
+    fractional_scale_globals: Option<(WpFractionalScaleManagerV1, WpViewporter)>,
+    /// Present if `fractional_scale_globals` is.
+    surface_scaler: Option<(WpFractionalScaleV1, WpViewport)>,
+    scale: f64,
+    has_focus: bool,
     exit: bool,
     configured: bool,
+    /// Start of the fade-in, `None` once it is over.
+    fade_start: Option<Instant>,
     width: u32,
     height: u32,
     damaged: bool,
 
     menu: menu::Menu,
     config: config::Config,
+    emit_socket: Option<PathBuf>,
+    print_selection: bool,
+    visibility: Visibility,
+    initial_keys: Option<String>,
+    geometry: Option<Geometry>,
+    /// Given to reloaded menus, see `Menu::set_dynamic_sender`.
+    dynamic_sender: Sender<menu::DynamicOutput>,
+    /// The submenu key being held, with `preview_ms`.
+    preview: Option<Preview>,
+    /// Fires when the submenu key has been held long enough.
+    preview_timer: Option<RegistrationToken>,
 
     modifiers: ModifierState,
+    /// The last key press acted upon, for `debounce_ms`.
+    last_press: Option<KeyPress>,
+    keymap: Option<keymap::Keymap>,
+    /// Index of the active layout in `keymap`.
+    kbd_layout: u32,
+}
+
+/// A press of the key of a submenu, which shows the submenu while held long enough.
+struct Preview {
+    /// Key code of the held key. Its keysym may change with the modifiers before it is released.
+    raw_code: u32,
+    page: usize,
+    /// The page the key was pressed in.
+    parent: usize,
+    /// Whether the key has been held long enough and the submenu is shown.
+    shown: bool,
+}
+
+impl Preview {
+    /// The page to go to when `raw_code` is released, if it is the held key: the submenu after a
+    /// short press, or back to the page the key was pressed in once the submenu was previewed.
+    fn released(&self, raw_code: u32) -> Option<usize> {
+        (raw_code == self.raw_code).then_some(match self.shown {
+            true => self.parent,
+            false => self.page,
+        })
+    }
 }
 
 impl State {
     fn draw(&mut self, _conn: &Connection, qh: &QueueHandle<State>) {
-        if !self.configured {
+        if !self.configured || self.exit {
             return;
         }
 
@@ -188,35 +643,41 @@ impl State {
             return;
         }
 
-        let scale = self.surface_scale;
+        let scale = self.scale;
 
         let width_f = self.width as f64;
         let height_f = self.height as f64;
 
+        // The buffer is in device pixels, while everything below is drawn in surface-local
+        // coordinates, scaled by cairo.
+        let (buffer_width, buffer_height) = buffer_size(self.width, self.height, scale);
+        let stride = buffer_width * 4;
+
         let (buffer, canvas) = self
             .pool
-            .create_buffer(
-                (self.width * scale) as i32,
-                (self.height * scale) as i32,
-                (self.width * 4 * scale) as i32,
-                Format::Argb8888,
-            )
+            .create_buffer(buffer_width, buffer_height, stride, Format::Argb8888)
             .expect("could not allocate frame shm buffer");
 
         let cairo_surf = unsafe {
             cairo::ImageSurface::create_for_data_unsafe(
                 canvas.as_mut_ptr(),
                 cairo::Format::ARgb32,
-                (self.width/*  * scale */) as i32,
-                (self.height/*  * scale */) as i32,
-                (self.width * 4/*  * scale */) as i32,
+                buffer_width,
+                buffer_height,
+                stride,
             )
             .expect("cairo surface")
         };
 
         let cairo_ctx = cairo::Context::new(&cairo_surf).expect("cairo context");
-        cairo_ctx.scale(scale as f64, scale as f64);
-        self.layer_surface.wl_surface().set_buffer_scale(scale as i32);
+        cairo_ctx.scale(scale, scale);
+        match &self.surface_scaler {
+            Some((_, viewport)) => viewport.set_destination(self.width as i32, self.height as i32),
+            None => self
+                .layer_surface
+                .wl_surface()
+                .set_buffer_scale(scale as i32),
+        }
 
         // background with rounded corners
         cairo_ctx.save().unwrap();
@@ -225,49 +686,72 @@ impl State {
         cairo_ctx.paint().unwrap();
         cairo_ctx.restore().unwrap();
 
-        cairo_ctx.new_sub_path();
-        let half_border = self.config.border_width * 0.5;
-        let r = self.config.corner_r;
-        cairo_ctx.arc(r + half_border, r + half_border, r, PI, 3.0 * FRAC_PI_2);
-        cairo_ctx.arc(
-            width_f - r - half_border,
-            r + half_border,
-            r,
-            3.0 * FRAC_PI_2,
-            TAU,
-        );
-        cairo_ctx.arc(
-            width_f - r - half_border,
-            height_f - r - half_border,
-            r,
-            0.0,
-            FRAC_PI_2,
-        );
-        cairo_ctx.arc(
-            r + half_border,
-            height_f - r - half_border,
-            r,
-            FRAC_PI_2,
-            PI,
-        );
-        cairo_ctx.close_path();
-        self.config.background.apply(&cairo_ctx);
-        cairo_ctx.fill_preserve().unwrap();
-        self.config.border.apply(&cairo_ctx);
-        cairo_ctx.set_line_width(self.config.border_width);
-        cairo_ctx.stroke().unwrap();
+        // Draw everything into a group first so that overlapping parts do not add up when
+        // painting it with the configured opacity.
+        cairo_ctx.push_group();
+
+        // The menu is surrounded by room for its shadow.
+        let [top, right, bottom, left] = self.config.shadow_margins();
+        let menu_width = width_f - left - right;
+        let menu_height = height_f - top - bottom;
+        let radii = self.config.corner_radii();
+        if let Some(shadow) = &self.config.shadow {
+            draw_shadow(
+                &cairo_ctx,
+                shadow,
+                (left, top, menu_width, menu_height),
+                radii,
+            );
+        }
+        cairo_ctx.save().unwrap();
+        cairo_ctx.translate(left, top);
+
+        let border_widths = self.config.border_widths();
+        if border_widths
+            .iter()
+            .all(|&width| width == self.config.border_width)
+        {
+            let half_border = self.config.border_width * 0.5;
+            rounded_rect(
+                &cairo_ctx,
+                (
+                    half_border,
+                    half_border,
+                    menu_width - self.config.border_width,
+                    menu_height - self.config.border_width,
+                ),
+                radii,
+            );
+            self.config.background.apply(&cairo_ctx);
+            cairo_ctx.fill_preserve().unwrap();
+            self.config.border.apply(&cairo_ctx);
+            cairo_ctx.set_line_width(self.config.border_width);
+            cairo_ctx.stroke().unwrap();
+        } else {
+            draw_uneven_border(
+                &cairo_ctx,
+                &self.config,
+                (menu_width, menu_height),
+                border_widths,
+                radii,
+            );
+        }
 
         // draw our menu
         self.menu.render(&self.config, &cairo_ctx).unwrap();
 
+        cairo_ctx.restore().unwrap();
+        cairo_ctx.pop_group_to_source().unwrap();
+        cairo_ctx
+            .paint_with_alpha(self.config.opacity * self.fade_in_alpha())
+            .unwrap();
+
         // Damage the entire window
-        self.layer_surface.wl_surface().damage_buffer(
-            0,
-            0,
-            (self.width * scale) as i32,
-            (self.height * scale) as i32,
-        );
-        self.damaged = false;
+        self.layer_surface
+            .wl_surface()
+            .damage_buffer(0, 0, buffer_width, buffer_height);
+        // Keep drawing on every frame until the fade-in is over.
+        self.damaged = self.fade_start.is_some();
 
         self.layer_surface
             .wl_surface()
@@ -278,26 +762,335 @@ impl State {
         self.layer_surface.wl_surface().commit();
     }
 
-    fn handle_action(&mut self, _conn: &Connection, action: menu::Action) {
+    /// Opacity factor of the fade-in. Ends the fade-in once it reaches 1.
+    fn fade_in_alpha(&mut self) -> f64 {
+        let alpha = self
+            .fade_start
+            .zip(self.config.fade_in_ms)
+            .and_then(|(start, duration)| fade_alpha(start.elapsed(), duration));
+        if alpha.is_none() {
+            self.fade_start = None;
+        }
+        alpha.unwrap_or(1.0)
+    }
+
+    /// Called while a key is held. Only entries with `repeat` run their command again.
+    fn repeat_key(&mut self, qh: &QueueHandle<Self>, event: KeyEvent) {
+        if self.exit || self.visibility.hidden {
+            return;
+        }
+        if let Some(action) = self.menu.get_repeat_action(self.modifiers, event.keysym) {
+            self.handle_action(qh, action);
+        }
+    }
+
+    fn handle_action(&mut self, qh: &QueueHandle<Self>, action: menu::Action) {
         match action {
-            menu::Action::Quit => {
-                self.exit = true;
-            }
-            menu::Action::Exec { cmd, keep_open } => {
-                exec(&cmd);
+            menu::Action::Quit => self.close(),
+            menu::Action::Exec {
+                cmd,
+                keep_open,
+                keep_going,
+                show_output,
+                desc,
+                path,
+                submenu,
+                ..
+            } => {
+                let cmd_line = chain_repr(&cmd, keep_going);
+                match &self.emit_socket {
+                    Some(socket) => emit(socket, &cmd_line, &desc, &path),
+                    None if self.print_selection => println!("{cmd_line}"),
+                    None if show_output => {
+                        let status = exec_capture(&self.config, &cmd, keep_going, &desc, &path)
+                            .unwrap_or_else(|err| format!("Failed to run '{cmd_line}': {err}"));
+                        self.menu.set_status(&status);
+                        self.resize();
+                    }
+                    None => {
+                        if let Err(err) = exec(&self.config, &cmd, keep_going, &desc, &path) {
+                            eprintln!("Failed to run '{cmd_line}': {err}");
+                            return;
+                        }
+                    }
+                }
+                if let Some(page) = submenu {
+                    self.handle_action(qh, menu::Action::Submenu(page));
+                }
                 if !keep_open {
-                    self.exit = true;
+                    self.close();
                 }
             }
-            menu::Action::Submenu(page) => {
+            menu::Action::Submenu(page) | menu::Action::Confirm(page) => {
                 self.menu.set_page(page);
-                self.width = self.menu.width(&self.config) as u32;
-                self.height = self.menu.height(&self.config) as u32;
-                self.layer_surface.set_size(self.width, self.height);
-                self.layer_surface.commit();
-                self.damaged = true;
+                self.update_anchor();
+                // Dynamic entries may have been regenerated with the keys from the config.
+                self.update_kbd_layout();
+                self.resize();
+            }
+            menu::Action::Search => {
+                self.menu.start_search();
+                self.resize();
+            }
+            menu::Action::Scroll(delta) => {
+                if self.menu.scroll_by(delta) {
+                    self.resize();
+                }
+            }
+            menu::Action::Reload => self.reload_config(),
+            menu::Action::Copy(text) => {
+                self.copy(qh, text);
+                self.close();
+                if self.exit && self.copy_source.is_some() {
+                    // Hide the menu while waiting for the copied text to be replaced.
+                    self.hide();
+                }
+            }
+        }
+    }
+
+    /// Wait for the key of the submenu `page` to be released or held for `preview_ms`.
+    fn start_preview(&mut self, raw_code: u32, page: usize, preview_ms: u64) {
+        let timer = Timer::from_duration(Duration::from_millis(preview_ms));
+        let token = self
+            .loop_handle
+            .insert_source(timer, |_, _, state| {
+                state.preview_timer = None;
+                if let Some(preview) = &mut state.preview {
+                    preview.shown = true;
+                    let page = preview.page;
+                    state.menu.set_page(page);
+                    state.update_anchor();
+                    state.update_kbd_layout();
+                    state.resize();
+                }
+                TimeoutAction::Drop
+            })
+            .expect("Failed to insert timer");
+        self.preview = Some(Preview {
+            raw_code,
+            page,
+            parent: self.menu.page(),
+            shown: false,
+        });
+        self.preview_timer = Some(token);
+    }
+
+    /// Exit, or only hide the menu in daemon mode.
+    fn close(&mut self) {
+        match self.visibility.close() {
+            true => self.exit = true,
+            false => self.hide(),
+        }
+    }
+
+    /// Unmap the surface. It is mapped again by the next commit, once the compositor has
+    /// configured it.
+    fn hide(&mut self) {
+        self.visibility.hidden = true;
+        self.configured = false;
+        // The compositor takes the focus away, which must not close the menu again.
+        self.has_focus = false;
+        self.layer_surface.wl_surface().attach(None, 0, 0);
+        self.layer_surface.commit();
+    }
+
+    /// Show the menu again after `hide`, starting from the top-level menu or the initial keys.
+    fn show(&mut self, qh: &QueueHandle<Self>) {
+        if !self.visibility.show() {
+            return;
+        }
+        self.menu.set_page(0);
+        if let Some(initial_keys) = self.initial_keys.clone() {
+            match self.menu.navigate_to_key_sequence(&initial_keys) {
+                Ok(Some(action)) => self.handle_action(qh, action),
+                Ok(None) => (),
+                Err(err) => eprintln!("Warning: {err:#}"),
+            }
+            // The initial keys ran a command which closed the menu.
+            if self.visibility.hidden {
+                return;
+            }
+        }
+        self.fade_start = self.config.fade_in_ms.map(|_| Instant::now());
+        self.last_press = None;
+        self.update_anchor();
+        self.update_kbd_layout();
+        self.resize();
+    }
+
+    /// The item at the given surface-local position.
+    fn item_at(&self, x: f64, y: f64) -> Option<usize> {
+        let [top, _, _, left] = self.config.shadow_margins();
+        self.menu.item_at(x - left, y - top)
+    }
+
+    /// Offer `text` as the clipboard contents.
+    fn copy(&mut self, qh: &QueueHandle<Self>, text: String) {
+        let (Some(manager), Some(device)) = (&self.data_device_manager, &self.data_device) else {
+            eprintln!("Warning: cannot copy, the compositor does not support the clipboard");
+            return;
+        };
+        let source = manager.create_copy_paste_source(qh, COPY_MIME_TYPES);
+        source.set_selection(device, self.last_serial);
+        self.copy_source = Some((source, text));
+    }
+
+    /// Show a hand over entries and the default cursor elsewhere, if the compositor supports
+    /// cursor shapes. `serial` is the one of the last pointer enter event.
+    fn set_cursor_shape(&mut self, serial: u32, over_entry: bool) {
+        let Some(device) = &self.cursor_shape_device else {
+            return;
+        };
+        if let Some(shape) = cursor_shape_change(self.cursor_shape, serial, over_entry) {
+            device.set_shape(serial, shape);
+            self.cursor_shape = Some((shape, serial));
+        }
+    }
+
+    fn set_hovered(&mut self, conn: &Connection, qh: &QueueHandle<Self>, item: Option<usize>) {
+        if self.menu.set_hovered(item) {
+            self.damaged = true;
+            self.draw(conn, qh);
+        }
+    }
+
+    /// The anchor of the current menu page.
+    fn anchor(&self) -> config::ConfigAnchor {
+        self.menu.anchor().unwrap_or(self.config.anchor)
+    }
+
+    /// Move the surface to the anchor of the current menu page. Applied on the next commit.
+    fn update_anchor(&mut self) {
+        let anchor = self.anchor();
+        self.layer_surface.set_anchor(anchor.into());
+        let [top, right, bottom, left] = self.config.margins(anchor);
+        self.layer_surface.set_margin(top, right, bottom, left);
+    }
+
+    /// Request a new surface size matching the current menu page.
+    fn resize(&mut self) {
+        (self.width, self.height) = surface_size(&self.menu, &self.config);
+        self.layer_surface.set_size(self.width, self.height);
+        self.layer_surface.commit();
+        self.damaged = true;
+    }
+
+    /// Show the keys as they are on the active keyboard layout, if `auto_kbd_layout` is set.
+    fn update_kbd_layout(&mut self) {
+        if !self.config.auto_kbd_layout {
+            return;
+        }
+        let Some(keymap) = &self.keymap else {
+            return;
+        };
+        let layout = self.kbd_layout;
+        self.menu
+            .set_key_translation(|keysym| keymap.translate(keysym, layout));
+        self.resize();
+    }
+
+    /// Move the menu to `output` if it is the one requested in the config.
+    fn check_output(&mut self, qh: &QueueHandle<Self>, output: &WlOutput) {
+        if self.config.output.is_none() || self.layer_output.as_ref() == Some(output) {
+            return;
+        }
+        let name = self.output.info(output).and_then(|info| info.name);
+        if !is_configured_output(&self.config, name.as_deref()) {
+            return;
+        }
+
+        // Layer surfaces cannot change their output, so a new one has to be created.
+        for inhibitor in self.keyboard_shortcuts_inhibitors.values() {
+            inhibitor.destroy();
+        }
+        if let Some((fractional_scale, viewport)) = &self.surface_scaler {
+            fractional_scale.destroy();
+            viewport.destroy();
+        }
+        self.layer_surface = create_layer_surface(
+            &self.wl_compositor,
+            &self.layer_shell,
+            qh,
+            &self.config,
+            self.anchor(),
+            Some(output),
+            (self.width, self.height),
+        );
+        if let Some(inhibit_manager) = &self.keyboard_shortcuts_inhibit_manager {
+            for (seat, inhibitor) in &mut self.keyboard_shortcuts_inhibitors {
+                *inhibitor = inhibit_manager.inhibit_shortcuts(
+                    self.layer_surface.wl_surface(),
+                    seat,
+                    qh,
+                    (),
+                );
             }
         }
+        self.surface_scaler = self
+            .fractional_scale_globals
+            .as_ref()
+            .map(|globals| create_surface_scaler(globals, &self.layer_surface, qh));
+        self.layer_output = Some(output.clone());
+        self.configured = false;
+        self.damaged = true;
+    }
+
+    /// Re-read the config file and rebuild the menu, keeping the current one on failure.
+    fn reload_config(&mut self) {
+        match reload(&mut self.config, &mut self.menu) {
+            Ok(()) => {
+                self.menu.set_dynamic_sender(self.dynamic_sender.clone());
+                if let Some(geometry) = self.geometry {
+                    geometry.apply(&mut self.config, &mut self.menu);
+                }
+                self.update_anchor();
+                self.update_kbd_layout();
+                self.resize();
+            }
+            Err(err) => eprintln!("Warning: failed to reload config: {err:#}"),
+        }
+    }
+}
+
+/// Keep the keyboard made by `create` for `seat`, unless the seat has one already. Returns whether
+/// a keyboard was added.
+fn add_keyboard(
+    keyboards: &mut HashMap<WlSeat, WlKeyboard>,
+    seat: &WlSeat,
+    create: impl FnOnce() -> WlKeyboard,
+) -> bool {
+    if keyboards.contains_key(seat) {
+        return false;
+    }
+    keyboards.insert(seat.clone(), create());
+    true
+}
+
+/// Release the keyboard of `seat`, if it has one.
+fn remove_keyboard(keyboards: &mut HashMap<WlSeat, WlKeyboard>, seat: &WlSeat) {
+    if let Some(keyboard) = keyboards.remove(seat) {
+        keyboard.release();
+    }
+}
+
+/// Whether the menu is shown. With `--daemon`, closing the menu only hides it until the next
+/// SIGUSR1.
+struct Visibility {
+    daemon: bool,
+    hidden: bool,
+}
+
+impl Visibility {
+    /// Close the menu. Returns whether the process should exit, otherwise the menu is hidden.
+    fn close(&mut self) -> bool {
+        self.hidden |= self.daemon;
+        !self.daemon
+    }
+
+    /// Show the menu again. Returns `false` if it was not hidden.
+    fn show(&mut self) -> bool {
+        std::mem::take(&mut self.hidden)
     }
 }
 
@@ -325,6 +1118,62 @@ impl Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()> for State {
     }
 }
 
+impl Dispatch<WpFractionalScaleManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: <WpFractionalScaleV1 as Proxy>::Event,
+        _data: &(),
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            let scale = fractional_scale(scale);
+            if scale != state.scale {
+                state.scale = scale;
+                state.damaged = true;
+                state.draw(conn, qh);
+            }
+        }
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpViewport, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
 impl CompositorHandler for State {
     fn scale_factor_changed(
         &mut self,
@@ -333,9 +1182,13 @@ impl CompositorHandler for State {
         _surface: &wayland_client::protocol::wl_surface::WlSurface,
         new_factor: i32,
     ) {
-        let scale = new_factor as u32;
-        if scale != self.surface_scale {
-            self.surface_scale = scale;
+        // The preferred fractional scale takes precedence.
+        if self.surface_scaler.is_some() {
+            return;
+        }
+        let scale = new_factor as f64;
+        if scale != self.scale {
+            self.scale = scale;
             self.damaged = true;
         }
     }
@@ -364,8 +1217,26 @@ impl CompositorHandler for State {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _surface: &wayland_client::protocol::wl_surface::WlSurface,
-        _output: &wayland_client::protocol::wl_output::WlOutput,
+        output: &wayland_client::protocol::wl_output::WlOutput,
     ) {
+        let Some(output_size) = self.output.info(output).and_then(|info| info.logical_size) else {
+            return;
+        };
+        if self.config.width_fraction.is_some() || self.config.height_fraction.is_some() {
+            self.menu
+                .set_fixed_size(self.config.fraction_size(output_size));
+            self.resize();
+        }
+        if self.config.max_height.is_some() || self.config.height_fraction.is_some() {
+            return;
+        }
+        let (_, height) = output_size;
+        let [shadow_top, _, shadow_bottom, _] = self.config.shadow_margins();
+        let [margin_top, _, margin_bottom, _] = self.config.margins(self.anchor());
+        let height = height - margin_top - margin_bottom;
+        self.menu
+            .set_output_height(height as f64 - shadow_top - shadow_bottom);
+        self.resize();
     }
 
     fn surface_leave(
@@ -386,17 +1257,19 @@ impl OutputHandler for State {
     fn new_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wayland_client::protocol::wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wayland_client::protocol::wl_output::WlOutput,
     ) {
+        self.check_output(qh, &output);
     }
 
     fn update_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wayland_client::protocol::wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wayland_client::protocol::wl_output::WlOutput,
     ) {
+        self.check_output(qh, &output);
     }
 
     fn output_destroyed(
@@ -424,6 +1297,12 @@ impl LayerShellHandler for State {
         let size = configure.new_size;
         self.width = size.0;
         self.height = size.1;
+        // The fade-in starts when the menu is shown.
+        if !self.configured
+            && let Some(start) = &mut self.fade_start
+        {
+            *start = Instant::now();
+        }
         self.configured = true;
         self.draw(conn, qh);
     }
@@ -488,6 +1367,7 @@ impl SeatHandler for State {
         if let Some(inhibitor) = self.keyboard_shortcuts_inhibitors.remove(&seat) {
             inhibitor.destroy();
         }
+        remove_keyboard(&mut self.keyboards, &seat);
     }
 
     fn new_capability(
@@ -497,12 +1377,38 @@ impl SeatHandler for State {
         seat: wayland_client::protocol::wl_seat::WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_none() {
-            let keyboard = self
-                .seat
-                .get_keyboard(qh, &seat, None)
-                .expect("Failed to create keyboard");
-            self.keyboard = Some(keyboard.clone());
+        if capability == Capability::Keyboard {
+            let added = add_keyboard(&mut self.keyboards, &seat, || {
+                let repeat_qh = qh.clone();
+                self.seat
+                    .get_keyboard_with_repeat(
+                        qh,
+                        &seat,
+                        None,
+                        self.loop_handle.clone(),
+                        Box::new(move |state, _keyboard, event| {
+                            state.repeat_key(&repeat_qh, event)
+                        }),
+                    )
+                    .expect("Failed to create keyboard")
+            });
+            // Copied text is offered on the first seat with a keyboard.
+            if added
+                && self.data_device.is_none()
+                && let Some(manager) = &self.data_device_manager
+            {
+                self.data_device = Some(manager.get_data_device(qh, &seat));
+            }
+        }
+        if capability == Capability::Pointer && self.pointer.is_none() {
+            let pointer = self
+                .seat
+                .get_pointer(qh, &seat)
+                .expect("Failed to create pointer");
+            if let Some(manager) = &self.cursor_shape_manager {
+                self.cursor_shape_device = Some(manager.get_shape_device(&pointer, qh));
+            }
+            self.pointer = Some(pointer);
         }
     }
 
@@ -510,15 +1416,191 @@ impl SeatHandler for State {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _seat: wayland_client::protocol::wl_seat::WlSeat,
+        seat: wayland_client::protocol::wl_seat::WlSeat,
         capability: Capability,
     ) {
-        if capability == Capability::Keyboard && self.keyboard.is_some() {
-            self.keyboard.take().unwrap().release();
+        if capability == Capability::Keyboard {
+            remove_keyboard(&mut self.keyboards, &seat);
+        }
+        if capability == Capability::Pointer && self.pointer.is_some() {
+            if let Some(device) = self.cursor_shape_device.take() {
+                device.destroy();
+            }
+            self.cursor_shape = None;
+            self.pointer.take().unwrap().release();
+        }
+    }
+}
+
+impl PointerHandler for State {
+    fn pointer_frame(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        _pointer: &WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            let (x, y) = event.position;
+            match event.kind {
+                PointerEventKind::Enter { serial } => {
+                    let item = self.item_at(x, y);
+                    self.set_cursor_shape(serial, item.is_some());
+                    self.set_hovered(conn, qh, item);
+                }
+                PointerEventKind::Motion { .. } => {
+                    let item = self.item_at(x, y);
+                    if let Some((_, serial)) = self.cursor_shape {
+                        self.set_cursor_shape(serial, item.is_some());
+                    }
+                    self.set_hovered(conn, qh, item);
+                }
+                PointerEventKind::Leave { .. } => self.set_hovered(conn, qh, None),
+                PointerEventKind::Press {
+                    button: BTN_LEFT,
+                    serial,
+                    ..
+                } => {
+                    self.last_serial = serial;
+                    let action = self
+                        .item_at(x, y)
+                        .and_then(|item| self.menu.item_action(item));
+                    if let Some(action) = action {
+                        self.handle_action(qh, action);
+                    }
+                }
+                PointerEventKind::Axis { vertical, .. } if vertical.discrete != 0 => {
+                    let action = menu::Action::Scroll(vertical.discrete.signum() as isize);
+                    self.handle_action(qh, action);
+                }
+                _ => (),
+            }
         }
     }
 }
 
+impl DataDeviceHandler for State {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+        _x: f64,
+        _y: f64,
+        _wl_surface: &wayland_client::protocol::wl_surface::WlSurface,
+    ) {
+    }
+
+    fn leave(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _data_device: &WlDataDevice) {}
+
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+        _x: f64,
+        _y: f64,
+    ) {
+    }
+
+    fn selection(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+    ) {
+    }
+
+    fn drop_performed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+    ) {
+    }
+}
+
+impl DataOfferHandler for State {
+    fn source_actions(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _offer: &mut DragOffer,
+        _actions: DndAction,
+    ) {
+    }
+
+    fn selected_action(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _offer: &mut DragOffer,
+        _actions: DndAction,
+    ) {
+    }
+}
+
+impl DataSourceHandler for State {
+    fn accept_mime(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &WlDataSource,
+        _mime: Option<String>,
+    ) {
+    }
+
+    fn send_request(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        source: &WlDataSource,
+        _mime: String,
+        mut fd: WritePipe,
+    ) {
+        let Some((copy_source, text)) = &self.copy_source else {
+            return;
+        };
+        if copy_source.inner() != source {
+            return;
+        }
+        if let Err(err) = fd.write_all(text.as_bytes()) {
+            eprintln!("Warning: failed to send copied text: {err}");
+        }
+    }
+
+    fn cancelled(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, source: &WlDataSource) {
+        // Something else was copied.
+        if self
+            .copy_source
+            .as_ref()
+            .is_some_and(|(copy_source, _)| copy_source.inner() == source)
+        {
+            self.copy_source = None;
+        }
+    }
+
+    fn dnd_dropped(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _source: &WlDataSource) {
+    }
+
+    fn dnd_finished(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &WlDataSource,
+    ) {
+    }
+
+    fn action(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &WlDataSource,
+        _action: DndAction,
+    ) {
+    }
+}
+
 impl KeyboardHandler for State {
     fn enter(
         &mut self,
@@ -528,8 +1610,14 @@ impl KeyboardHandler for State {
         _surface: &wayland_client::protocol::wl_surface::WlSurface,
         _serial: u32,
         _raw: &[u32],
-        _keysyms: &[smithay_client_toolkit::seat::keyboard::Keysym],
+        keysyms: &[smithay_client_toolkit::seat::keyboard::Keysym],
     ) {
+        self.has_focus = true;
+        // Modifiers may already be held, e.g. from the shortcut which started us.
+        self.modifiers.sides = ModifierSides::default();
+        for &keysym in keysyms {
+            self.modifiers.update_key(keysym, true);
+        }
     }
 
     fn leave(
@@ -540,34 +1628,79 @@ impl KeyboardHandler for State {
         _surface: &wayland_client::protocol::wl_surface::WlSurface,
         _serial: u32,
     ) {
+        // The shortcuts inhibitor is only active while we have focus, so there is nothing to undo
+        // here.
+        if closes_on_focus_loss(self.has_focus, &self.config) {
+            self.close();
+        }
+        self.has_focus = false;
     }
 
     fn press_key(
         &mut self,
-        conn: &Connection,
-        _qh: &wayland_client::QueueHandle<Self>,
+        _conn: &Connection,
+        qh: &wayland_client::QueueHandle<Self>,
         _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
-        _serial: u32,
+        serial: u32,
         event: smithay_client_toolkit::seat::keyboard::KeyEvent,
     ) {
-        let action = if let Some(action) = self.menu.get_action(self.modifiers, event.keysym) {
-            Some(action)
-        } else {
-            None
-        };
-        if let Some(action) = action {
-            self.handle_action(conn, action);
+        self.last_serial = serial;
+        self.modifiers.update_key(event.keysym, true);
+        if self.fade_start.take().is_some() {
+            self.damaged = true;
+        }
+        if let Some(debounce_ms) = self.config.debounce_ms {
+            let press = (event.keysym, self.modifiers, event.time);
+            if should_debounce(self.last_press, press, debounce_ms) {
+                return;
+            }
+            self.last_press = Some(press);
+        }
+        if self.menu.is_searching() {
+            let action = self.menu.search_input(event.keysym, event.utf8.as_deref());
+            self.resize();
+            if let Some(action) = action {
+                self.handle_action(qh, action);
+            }
+            return;
+        }
+
+        // Other keys are ignored while a submenu key is held.
+        if self.preview.is_some() {
+            return;
+        }
+        if let Some(action) = self.menu.get_action(self.modifiers, event.keysym) {
+            if let menu::Action::Submenu(page) = action
+                && let Some(preview_ms) = self.config.preview_ms
+                && self.menu.parent_page(page) == Some(self.menu.page())
+            {
+                self.start_preview(event.raw_code, page, preview_ms);
+                return;
+            }
+            self.handle_action(qh, action);
         }
     }
 
     fn release_key(
         &mut self,
         _conn: &Connection,
-        _qh: &wayland_client::QueueHandle<Self>,
+        qh: &wayland_client::QueueHandle<Self>,
         _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
-        _event: smithay_client_toolkit::seat::keyboard::KeyEvent,
+        event: smithay_client_toolkit::seat::keyboard::KeyEvent,
     ) {
+        self.modifiers.update_key(event.keysym, false);
+        if let Some(page) = self
+            .preview
+            .as_ref()
+            .and_then(|p| p.released(event.raw_code))
+        {
+            self.preview = None;
+            if let Some(timer) = self.preview_timer.take() {
+                self.loop_handle.remove(timer);
+            }
+            self.handle_action(qh, menu::Action::Submenu(page));
+        }
     }
 
     fn update_modifiers(
@@ -577,9 +1710,27 @@ impl KeyboardHandler for State {
         _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
         _serial: u32,
         modifiers: smithay_client_toolkit::seat::keyboard::Modifiers,
-        _layout: u32,
+        layout: u32,
+    ) {
+        self.modifiers = ModifierState::from_sctk_modifiers(&modifiers, self.modifiers.sides);
+        if layout != self.kbd_layout {
+            self.kbd_layout = layout;
+            self.update_kbd_layout();
+        }
+    }
+
+    fn update_keymap(
+        &mut self,
+        _conn: &Connection,
+        _qh: &wayland_client::QueueHandle<Self>,
+        _keyboard: &wayland_client::protocol::wl_keyboard::WlKeyboard,
+        keymap: smithay_client_toolkit::seat::keyboard::Keymap<'_>,
     ) {
-        self.modifiers = ModifierState::from_sctk_modifiers(&modifiers);
+        self.keymap = keymap::Keymap::from_string(keymap.as_string());
+        if self.keymap.is_none() && self.config.auto_kbd_layout {
+            eprintln!("Warning: failed to compile the keymap, keys are shown as configured");
+        }
+        self.update_kbd_layout();
     }
 }
 
@@ -588,13 +1739,433 @@ delegate_output!(State);
 delegate_shm!(State);
 delegate_seat!(State);
 delegate_keyboard!(State);
+delegate_pointer!(State);
+
+delegate_data_device!(State);
 
 delegate_layer!(State);
 delegate_registry!(State);
 
-fn exec(cmd: &str) {
-    let mut proc = Command::new("sh");
-    proc.args(["-c", cmd]);
+/// Re-read the config file and rebuild the menu. Both are left as they are on failure.
+fn reload(config: &mut config::Config, menu: &mut menu::Menu) -> anyhow::Result<()> {
+    if config.path.as_os_str().is_empty() {
+        bail!("a config read from stdin cannot be reloaded");
+    }
+    // Fonts may have been installed or removed since the menu was built.
+    text::clear_size_cache();
+    let new_config = config::Config::new(&config.path)?;
+    let new_menu = menu::Menu::new(&new_config)?;
+    new_config.warn_duplicate_keys();
+    *config = new_config;
+    *menu = new_menu;
+    Ok(())
+}
+
+/// Size of the layer surface: the menu plus room for its shadow.
+fn surface_size(menu: &menu::Menu, config: &config::Config) -> (u32, u32) {
+    let [top, right, bottom, left] = config.shadow_margins();
+    // Round up so that the border and the last row are never clipped.
+    (
+        (menu.width() + left + right).ceil() as u32,
+        (menu.height() + top + bottom).ceil() as u32,
+    )
+}
+
+/// Size in device pixels of the buffer for a surface of the given size.
+fn buffer_size(width: u32, height: u32, scale: f64) -> (i32, i32) {
+    (
+        (width as f64 * scale).round() as i32,
+        (height as f64 * scale).round() as i32,
+    )
+}
+
+/// Convert a preferred scale sent by the fractional scale protocol.
+fn fractional_scale(numerator: u32) -> f64 {
+    // The scale is sent as a numerator over 120.
+    numerator as f64 / 120.0
+}
+
+/// Whether `name` is the output the menu should be shown on.
+fn is_configured_output(config: &config::Config, name: Option<&str>) -> bool {
+    name.is_some() && config.output.as_deref() == name
+}
+
+/// Whether losing keyboard focus closes the menu.
+///
+/// Only a focus we actually had counts, so that a leave sent while the surface is being mapped
+/// does not close the menu.
+fn closes_on_focus_loss(had_focus: bool, config: &config::Config) -> bool {
+    had_focus && config.close_on_focus_loss
+}
+
+/// Fill the background and a border with a different width on each side, given in the order of
+/// `Config::border_widths`. Each side is the area between the outer edge of the menu and the
+/// background, whose corners are rounded by what is left of the radius by the wider side.
+fn draw_uneven_border(
+    cairo_ctx: &cairo::Context,
+    config: &config::Config,
+    (width, height): (f64, f64),
+    [top, right, bottom, left]: [f64; 4],
+    radii: [f64; 4],
+) {
+    let [top_left, top_right, bottom_right, bottom_left] = radii;
+    let inner_radii = [
+        top_left - top.max(left),
+        top_right - top.max(right),
+        bottom_right - bottom.max(right),
+        bottom_left - bottom.max(left),
+    ]
+    .map(|r| r.max(0.0));
+    let inner = (
+        left,
+        top,
+        (width - left - right).max(0.0),
+        (height - top - bottom).max(0.0),
+    );
+
+    rounded_rect(cairo_ctx, inner, inner_radii);
+    config.background.apply(cairo_ctx);
+    cairo_ctx.fill().unwrap();
+
+    cairo_ctx.save().unwrap();
+    cairo_ctx.set_fill_rule(cairo::FillRule::EvenOdd);
+    rounded_rect(cairo_ctx, (0.0, 0.0, width, height), radii);
+    rounded_rect(cairo_ctx, inner, inner_radii);
+    config.border.apply(cairo_ctx);
+    cairo_ctx.fill().unwrap();
+    cairo_ctx.restore().unwrap();
+}
+
+/// Add a rectangle with the given corner radii to the path of `cairo_ctx`. The radii are in the
+/// order of `Config::corner_radii`.
+fn rounded_rect(
+    cairo_ctx: &cairo::Context,
+    (x, y, width, height): (f64, f64, f64, f64),
+    radii: [f64; 4],
+) {
+    let [top_left, top_right, bottom_right, bottom_left] = radii;
+    cairo_ctx.new_sub_path();
+    cairo_ctx.arc(x + top_left, y + top_left, top_left, PI, 3.0 * FRAC_PI_2);
+    cairo_ctx.arc(
+        x + width - top_right,
+        y + top_right,
+        top_right,
+        3.0 * FRAC_PI_2,
+        TAU,
+    );
+    cairo_ctx.arc(
+        x + width - bottom_right,
+        y + height - bottom_right,
+        bottom_right,
+        0.0,
+        FRAC_PI_2,
+    );
+    cairo_ctx.arc(
+        x + bottom_left,
+        y + height - bottom_left,
+        bottom_left,
+        FRAC_PI_2,
+        PI,
+    );
+    cairo_ctx.close_path();
+}
+
+/// Approximate a blurred shadow of the menu at `rect` by stacking increasingly smaller, mostly
+/// transparent copies of its shape.
+fn draw_shadow(
+    cairo_ctx: &cairo::Context,
+    shadow: &config::Shadow,
+    (x, y, width, height): (f64, f64, f64, f64),
+    radii: [f64; 4],
+) {
+    const STEPS: u32 = 8;
+    let steps = if shadow.blur > 0.0 { STEPS } else { 1 };
+    shadow
+        .color
+        .scale_alpha(1.0 / steps as f64)
+        .apply(cairo_ctx);
+    for step in 0..steps {
+        let spread = shadow.blur * (steps - step) as f64 / steps as f64;
+        rounded_rect(
+            cairo_ctx,
+            (
+                x + shadow.offset_x - spread,
+                y + shadow.offset_y - spread,
+                width + spread * 2.0,
+                height + spread * 2.0,
+            ),
+            radii.map(|r| r + spread),
+        );
+        cairo_ctx.fill().unwrap();
+    }
+}
+
+/// MIME types offered for copied text.
+const COPY_MIME_TYPES: [&str; 5] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+
+fn create_layer_surface(
+    wl_compositor: &CompositorState,
+    layer_shell: &LayerShell,
+    qh: &QueueHandle<State>,
+    config: &config::Config,
+    anchor: config::ConfigAnchor,
+    output: Option<&WlOutput>,
+    (width, height): (u32, u32),
+) -> LayerSurface {
+    let surface = wl_compositor.create_surface(qh);
+
+    let layer_surface = layer_shell.create_layer_surface(
+        qh,
+        surface,
+        config.layer.into(),
+        Some("wlr_which_key"),
+        output,
+    );
+    layer_surface.set_anchor(anchor.into());
+    layer_surface.set_size(width, height);
+    let [top, right, bottom, left] = config.margins(anchor);
+    layer_surface.set_margin(top, right, bottom, left);
+    layer_surface.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+    if let Some(zone) = config.exclusive_zone {
+        layer_surface.set_exclusive_zone(zone);
+    }
+
+    layer_surface.commit();
+    layer_surface
+}
+
+/// The cursor shape to set for the pointer over an entry or not, if it differs from the `current`
+/// shape and serial.
+fn cursor_shape_change(
+    current: Option<(Shape, u32)>,
+    serial: u32,
+    over_entry: bool,
+) -> Option<Shape> {
+    let shape = match over_entry {
+        true => Shape::Pointer,
+        false => Shape::Default,
+    };
+    (current != Some((shape, serial))).then_some(shape)
+}
+
+/// A key press: its keysym, the modifiers held and its time in milliseconds.
+type KeyPress = (
+    smithay_client_toolkit::seat::keyboard::Keysym,
+    ModifierState,
+    u32,
+);
+
+/// Whether `press` repeats the `last` press acted upon within `debounce_ms`, so that it is
+/// ignored.
+fn should_debounce(last: Option<KeyPress>, press: KeyPress, debounce_ms: u64) -> bool {
+    last.is_some_and(|(keysym, modifiers, time)| {
+        keysym == press.0
+            && modifiers == press.1
+            && u64::from(press.2.wrapping_sub(time)) < debounce_ms
+    })
+}
+
+/// Opacity factor `elapsed` into a fade-in lasting `duration_ms`, eased out. `None` once the
+/// fade-in is over.
+fn fade_alpha(elapsed: Duration, duration_ms: u64) -> Option<f64> {
+    let t = elapsed.as_secs_f64() * 1000.0 / duration_ms as f64;
+    (t < 1.0).then(|| 1.0 - (1.0 - t).powi(3))
+}
+
+/// Write `entries` and their submenus to `out`, indented by nesting level.
+fn print_tree(
+    out: &mut impl Write,
+    entries: &[config::Entry],
+    keep_open_default: bool,
+    depth: usize,
+) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    for entry in entries {
+        match entry {
+            config::Entry::Cmd {
+                key,
+                cmd,
+                desc,
+                keep_open,
+                keep_going,
+                confirm,
+                ..
+            } => {
+                let cmd = chain_repr(cmd, *keep_going);
+                let keep_open = match keep_open.unwrap_or(keep_open_default) {
+                    true => ", keep open",
+                    false => "",
+                };
+                let confirm = if confirm.is_some() { ", confirm" } else { "" };
+                writeln!(
+                    out,
+                    "{indent}{key}: {desc} (cmd: {cmd}{keep_open}{confirm})"
+                )?;
+            }
+            config::Entry::Recursive {
+                key,
+                submenu,
+                cmd,
+                desc,
+                ..
+            } => {
+                match cmd {
+                    Some(cmd) => writeln!(
+                        out,
+                        "{indent}{key}: +{desc} (cmd: {}, submenu)",
+                        chain_repr(cmd, false)
+                    )?,
+                    None => writeln!(out, "{indent}{key}: +{desc} (submenu)")?,
+                }
+                print_tree(out, submenu, keep_open_default, depth + 1)?;
+            }
+            config::Entry::Reload { key, desc, .. } => {
+                writeln!(out, "{indent}{key}: {desc} (reload)")?
+            }
+            config::Entry::Copy {
+                key, text, desc, ..
+            } => writeln!(out, "{indent}{key}: {desc} (copy: {text})")?,
+            config::Entry::Dynamic { cmd, refresh } => {
+                let refresh = if *refresh { ", refreshed" } else { "" };
+                writeln!(out, "{indent}(entries from: {cmd}{refresh})")?
+            }
+            config::Entry::Separator { label } => match label {
+                Some(label) => writeln!(out, "{indent}--- {label} ---")?,
+                None => writeln!(out, "{indent}---")?,
+            },
+            config::Entry::Include { .. } => {
+                unreachable!("includes are resolved when the config is loaded")
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create the objects used to render `layer_surface` at its preferred fractional scale.
+fn create_surface_scaler(
+    (manager, viewporter): &(WpFractionalScaleManagerV1, WpViewporter),
+    layer_surface: &LayerSurface,
+    qh: &QueueHandle<State>,
+) -> (WpFractionalScaleV1, WpViewport) {
+    let surface = layer_surface.wl_surface();
+    (
+        manager.get_fractional_scale(surface, qh, ()),
+        viewporter.get_viewport(surface, qh, ()),
+    )
+}
+
+/// Build a command running `cmd` using the configured shell. The keys pressed to reach the entry
+/// and its description are passed in the environment.
+fn shell_command(config: &config::Config, cmd: &str, desc: &str, path: &[String]) -> Command {
+    let mut proc = Command::new(&config.shell[0]);
+    proc.args(&config.shell[1..]);
+    proc.arg(cmd);
+    proc.env("WLR_WHICH_KEY_SEQUENCE", path.join(" "));
+    proc.env("WLR_WHICH_KEY_DESC", desc);
+    if let Some(cwd) = &config.cwd {
+        if cwd.is_dir() {
+            proc.current_dir(cwd);
+        } else {
+            eprintln!(
+                "Warning: working directory {} does not exist, using the current one",
+                cwd.display()
+            );
+        }
+    }
+    proc
+}
+
+/// Longest tail of the output of a command kept by `exec_capture`, in bytes.
+const MAX_CAPTURED_OUTPUT: usize = 4096;
+/// Longest line shown by `exec_capture`, in characters.
+const MAX_STATUS_CHARS: usize = 120;
+
+/// A chain of commands as a single shell command line.
+fn chain_repr(cmds: &[String], keep_going: bool) -> String {
+    cmds.join(if keep_going { "; " } else { " && " })
+}
+
+/// Run all commands of a chain but the last one, waiting for each to finish. Returns the last
+/// command, or an error if one failed and `keep_going` is not set.
+fn exec_leading<'a>(
+    config: &config::Config,
+    cmds: &'a [String],
+    keep_going: bool,
+    desc: &str,
+    path: &[String],
+) -> io::Result<&'a str> {
+    let (last, leading) = cmds.split_last().expect("command chains are never empty");
+    for cmd in leading {
+        let status = shell_command(config, cmd, desc, path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()?;
+        if !status.success() && !keep_going {
+            return Err(io::Error::other(format!("'{cmd}' failed: {status}")));
+        }
+    }
+    Ok(last)
+}
+
+/// Run the commands in `cmds` and wait for them to finish. Returns the last non-empty line of the
+/// output of the last command, or its exit status if it failed without output.
+fn exec_capture(
+    config: &config::Config,
+    cmds: &[String],
+    keep_going: bool,
+    desc: &str,
+    path: &[String],
+) -> io::Result<String> {
+    let cmd = exec_leading(config, cmds, keep_going, desc, path)?;
+    let mut proc = shell_command(config, cmd, desc, path);
+    proc.stdin(Stdio::null());
+    proc.stdout(Stdio::piped());
+    let mut child = proc.spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut output = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let n = stdout.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..n]);
+        if output.len() > MAX_CAPTURED_OUTPUT {
+            output.drain(..output.len() - MAX_CAPTURED_OUTPUT);
+        }
+    }
+    let status = child.wait()?;
+
+    let output = String::from_utf8_lossy(&output);
+    let line = output.lines().rev().find(|line| !line.trim().is_empty());
+    Ok(match line {
+        Some(line) if line.chars().count() > MAX_STATUS_CHARS => {
+            let mut line: String = line.chars().take(MAX_STATUS_CHARS - 1).collect();
+            line.push('…');
+            line
+        }
+        Some(line) => line.to_owned(),
+        None if !status.success() => format!("'{cmd}' failed: {status}"),
+        None => String::new(),
+    })
+}
+
+/// Run the commands in `cmds`. All but the last one are waited for, the last one is detached.
+fn exec(
+    config: &config::Config,
+    cmds: &[String],
+    keep_going: bool,
+    desc: &str,
+    path: &[String],
+) -> io::Result<()> {
+    let cmd = exec_leading(config, cmds, keep_going, desc, path)?;
+    let mut proc = shell_command(config, cmd, desc, path);
     proc.stdin(Stdio::null());
     proc.stdout(Stdio::null());
     // Safety: libc::daemon() is async-signal-safe
@@ -604,5 +2175,528 @@ fn exec(cmd: &str) {
             _ => Ok(()),
         });
     }
-    proc.spawn().unwrap().wait().unwrap();
+    proc.spawn()?.wait()?;
+    Ok(())
+}
+
+/// Send the selected entry to the controller listening on `socket`.
+fn emit(socket: &Path, cmd: &str, desc: &str, path: &[String]) {
+    let line = serde_json::json!({
+        "path": path,
+        "cmd": cmd,
+        "desc": desc,
+    });
+    let result = UnixStream::connect(socket).and_then(|mut stream| writeln!(stream, "{line}"));
+    if let Err(err) = result {
+        eprintln!("Failed to emit selection to {}: {err}", socket.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surface_size_rounds_up() {
+        let config: config::Config = serde_yaml::from_str(
+            "padding: 10.2\nborder_width: 0\nmenu: [{ key: a, desc: A, cmd: a }]",
+        )
+        .unwrap();
+        let menu = menu::Menu::new(&config).unwrap();
+        let (width, height) = (menu.width(), menu.height());
+        assert_ne!(width.fract(), 0.0);
+        assert_ne!(height.fract(), 0.0);
+        assert_eq!(
+            surface_size(&menu, &config),
+            (width.ceil() as u32, height.ceil() as u32)
+        );
+    }
+
+    #[test]
+    fn emit_writes_json_line() {
+        use std::io::BufRead;
+        use std::os::unix::net::UnixListener;
+
+        let socket =
+            std::env::temp_dir().join(format!("wlr-which-key-emit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let path = ["Apps".to_owned(), "Browser".to_owned()];
+        emit(&socket, "firefox", "Firefox", &path);
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        io::BufReader::new(stream).read_line(&mut line).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "path": ["Apps", "Browser"],
+                "cmd": "firefox",
+                "desc": "Firefox",
+            })
+        );
+
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[test]
+    fn shell_command_appends_cmd() {
+        let mut config = config::Config::default();
+        let proc = shell_command(&config, "true", "", &[]);
+        assert_eq!(proc.get_program(), "sh");
+        assert_eq!(proc.get_args().collect::<Vec<_>>(), ["-c", "true"]);
+
+        config.shell = vec!["bash".to_owned(), "-e".to_owned(), "-c".to_owned()];
+        let proc = shell_command(&config, "echo hi", "", &[]);
+        assert_eq!(proc.get_program(), "bash");
+        assert_eq!(proc.get_args().collect::<Vec<_>>(), ["-e", "-c", "echo hi"]);
+    }
+
+    #[test]
+    fn shell_command_cwd() {
+        let mut config = config::Config {
+            cwd: Some(std::env::temp_dir()),
+            ..Default::default()
+        };
+        let proc = shell_command(&config, "true", "", &[]);
+        assert_eq!(proc.get_current_dir(), Some(std::env::temp_dir().as_path()));
+        // A missing directory falls back to the current one.
+        config.cwd = Some(PathBuf::from("/nonexistent/wlr-which-key"));
+        let proc = shell_command(&config, "true", "", &[]);
+        assert_eq!(proc.get_current_dir(), None);
+    }
+
+    #[test]
+    fn exec_missing_shell() {
+        let config = config::Config {
+            shell: vec!["/nonexistent/wlr-which-key-shell".to_owned()],
+            ..Default::default()
+        };
+        let err = exec(&config, &["true".to_owned()], false, "", &[]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn focus_loss() {
+        let mut config = config::Config::default();
+        assert!(!closes_on_focus_loss(true, &config));
+        config.close_on_focus_loss = true;
+        assert!(closes_on_focus_loss(true, &config));
+        // A leave before the first enter.
+        assert!(!closes_on_focus_loss(false, &config));
+    }
+
+    #[test]
+    fn configured_output() {
+        let config: config::Config = serde_yaml::from_str("output: DP-1\nmenu: []").unwrap();
+        assert_eq!(config.output.as_deref(), Some("DP-1"));
+        assert!(is_configured_output(&config, Some("DP-1")));
+        assert!(!is_configured_output(&config, Some("eDP-1")));
+        assert!(!is_configured_output(&config, None));
+
+        let config = config::Config::default();
+        assert!(!is_configured_output(&config, Some("DP-1")));
+        assert!(!is_configured_output(&config, None));
+    }
+
+    #[test]
+    fn scaled_buffer_size() {
+        assert_eq!(buffer_size(101, 50, 1.0), (101, 50));
+        assert_eq!(buffer_size(101, 50, 2.0), (202, 100));
+        assert_eq!(fractional_scale(180), 1.5);
+        assert_eq!(buffer_size(101, 50, fractional_scale(180)), (152, 75));
+    }
+
+    fn tree(config: &str) -> String {
+        let config: config::Config = serde_yaml::from_str(config).unwrap();
+        let mut out = Vec::new();
+        print_tree(&mut out, &config.menu, config.keep_open_default, 0).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn print_nested_tree() {
+        let tree = tree(
+            "menu:
+  - { key: f, desc: Firefox, cmd: firefox }
+  - key: p
+    desc: Power
+    submenu:
+      - { key: s, desc: Sleep, cmd: systemctl suspend, keep_open: true }",
+        );
+        assert_eq!(
+            tree,
+            "f: Firefox (cmd: firefox)
+p: +Power (submenu)
+  s: Sleep (cmd: systemctl suspend, keep open)
+"
+        );
+    }
+
+    #[test]
+    fn failed_reload_keeps_menu() {
+        use smithay_client_toolkit::seat::keyboard::Keysym;
+
+        let dir = std::env::temp_dir().join(format!("wlr-which-key-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        std::fs::write(&path, "menu: [{ key: a, desc: A, cmd: a }]").unwrap();
+        let mut config = config::Config::new(&path).unwrap();
+        let mut menu = menu::Menu::new(&config).unwrap();
+        let action = |menu: &menu::Menu, sym| menu.get_action(ModifierState::default(), sym);
+
+        std::fs::write(&path, "menu: [{ key: b, desc: B }]").unwrap();
+        assert!(reload(&mut config, &mut menu).is_err());
+        assert!(matches!(
+            action(&menu, Keysym::a),
+            Some(menu::Action::Exec { .. })
+        ));
+        assert_eq!(config.menu[0].desc(), "A");
+
+        std::fs::write(&path, "menu: [{ key: b, desc: B, reload: true }]").unwrap();
+        reload(&mut config, &mut menu).unwrap();
+        assert!(action(&menu, Keysym::a).is_none());
+        assert!(matches!(
+            action(&menu, Keysym::b),
+            Some(menu::Action::Reload)
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn print_reload_and_copy_entries() {
+        assert_eq!(
+            tree(
+                "menu: [{ key: r, desc: Reload, reload: true }, { key: c, desc: Copy, copy: text }]"
+            ),
+            "r: Reload (reload)\nc: Copy (copy: text)\n"
+        );
+    }
+
+    #[test]
+    fn surface_size_includes_shadow() {
+        let config: config::Config = serde_yaml::from_str(
+            "shadow: { blur: 5, offset_x: 2, offset_y: 8 }\nmenu: [{ key: a, desc: A, cmd: a }]",
+        )
+        .unwrap();
+        // The shadow is offset down and to the right, so it needs more room there.
+        assert_eq!(config.shadow_margins(), [0.0, 7.0, 13.0, 3.0]);
+        let menu = menu::Menu::new(&config).unwrap();
+        assert_eq!(
+            surface_size(&menu, &config),
+            (
+                (menu.width() + 10.0).ceil() as u32,
+                (menu.height() + 13.0).ceil() as u32
+            )
+        );
+    }
+
+    #[test]
+    fn print_separators() {
+        assert_eq!(
+            tree("menu: [{ separator: true }, { separator: Tools }]"),
+            "---\n--- Tools ---\n"
+        );
+    }
+
+    #[test]
+    fn print_dynamic_entries() {
+        assert_eq!(
+            tree("menu: [{ dynamic: ls }, { dynamic: date, refresh: true }]"),
+            "(entries from: ls)\n(entries from: date, refreshed)\n"
+        );
+    }
+
+    /// The status shown after running the chain `cmds` with `show_output`.
+    fn capture(cmds: &[&str], keep_going: bool) -> String {
+        let cmds: Vec<_> = cmds.iter().map(|&cmd| cmd.to_owned()).collect();
+        exec_capture(&config::Config::default(), &cmds, keep_going, "", &[]).unwrap()
+    }
+
+    #[test]
+    fn exec_capture_last_line() {
+        assert_eq!(capture(&["printf 'first\\nlast\\n\\n'"], false), "last");
+        assert_eq!(
+            capture(&["exit 3"], false),
+            "'exit 3' failed: exit status: 3"
+        );
+        assert_eq!(capture(&["true"], false), "");
+    }
+
+    #[test]
+    fn exec_capture_truncates() {
+        let line = capture(&["seq 10000 | tr -d '\\n'"], false);
+        assert_eq!(line.chars().count(), MAX_STATUS_CHARS);
+        assert!(line.ends_with('…'));
+    }
+
+    #[test]
+    fn command_environment() {
+        let config = config::Config::default();
+        let path = ["a".to_owned(), "Ctrl+b".to_owned()];
+        let cmd = "echo \"$WLR_WHICH_KEY_SEQUENCE|$WLR_WHICH_KEY_DESC\"".to_owned();
+        assert_eq!(
+            exec_capture(&config, &[cmd], false, "Open files", &path).unwrap(),
+            "a Ctrl+b|Open files"
+        );
+    }
+
+    #[test]
+    fn command_chain() {
+        assert_eq!(capture(&["echo a", "echo b"], false), "b");
+        let err = exec_capture(
+            &config::Config::default(),
+            &["false".to_owned(), "echo b".to_owned()],
+            false,
+            "",
+            &[],
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "'false' failed: exit status: 1");
+        assert_eq!(capture(&["false", "echo b"], true), "b");
+        let cmds = ["a".to_owned(), "b".to_owned()];
+        assert_eq!(chain_repr(&cmds, false), "a && b");
+        assert_eq!(chain_repr(&cmds, true), "a; b");
+    }
+
+    #[test]
+    fn stdin_config_is_not_reloaded() {
+        let mut config =
+            config::Config::from_yaml_str("menu: [{ key: a, desc: A, cmd: a }]").unwrap();
+        let mut menu = menu::Menu::new(&config).unwrap();
+        let err = reload(&mut config, &mut menu).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a config read from stdin cannot be reloaded"
+        );
+        assert_eq!(config.menu[0].desc(), "A");
+    }
+
+    #[test]
+    fn print_keep_open_default() {
+        let tree = tree(
+            "keep_open_default: true
+menu:
+  - { key: a, desc: A, cmd: a }
+  - { key: b, desc: B, cmd: b, keep_open: false }",
+        );
+        assert_eq!(tree, "a: A (cmd: a, keep open)\nb: B (cmd: b)\n");
+    }
+
+    #[test]
+    fn print_submenu_cmd() {
+        let tree = tree(
+            "menu: [{ key: m, desc: Music, cmd: [mpd, ncmpcpp], submenu: [{ key: p, desc: Pause, cmd: mpc pause }] }]",
+        );
+        assert_eq!(
+            tree,
+            "m: +Music (cmd: mpd && ncmpcpp, submenu)\n  p: Pause (cmd: mpc pause)\n"
+        );
+    }
+
+    #[test]
+    fn fade_in_eases_out() {
+        assert_eq!(fade_alpha(Duration::ZERO, 200), Some(0.0));
+        assert_eq!(fade_alpha(Duration::from_millis(100), 200), Some(0.875));
+        assert_eq!(fade_alpha(Duration::from_millis(200), 200), None);
+        assert_eq!(fade_alpha(Duration::ZERO, 0), None);
+    }
+
+    #[test]
+    fn print_command_alias() {
+        for flag in ["--print-selection", "--print-command"] {
+            let args = Args::try_parse_from(["wlr-which-key", flag]).unwrap();
+            assert!(args.print_selection);
+        }
+    }
+
+    #[test]
+    fn debug_layout_switch() {
+        let args = Args::try_parse_from(["wlr-which-key", "--debug-layout"]).unwrap();
+        assert!(debug_layout_requested(args.debug_layout, None));
+        let args = Args::try_parse_from(["wlr-which-key"]).unwrap();
+        assert!(!debug_layout_requested(args.debug_layout, None));
+        assert!(debug_layout_requested(false, Some("1")));
+        assert!(!debug_layout_requested(false, Some("0")));
+    }
+
+    #[test]
+    fn inhibit_shortcuts_flag() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(["wlr-which-key"].iter().chain(args))
+                .map(|args| args.inhibit_shortcuts)
+        };
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(
+            parse(&["--inhibit-shortcuts", "false"]).unwrap(),
+            Some(false)
+        );
+        assert_eq!(parse(&["--inhibit-shortcuts=true"]).unwrap(), Some(true));
+        assert!(parse(&["--inhibit-shortcuts"]).is_err());
+        assert!(parse(&["--inhibit-shortcuts", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn debounce_same_press() {
+        use smithay_client_toolkit::seat::keyboard::Keysym;
+        let none = ModifierState::default();
+        let ctrl = ModifierState {
+            mod_ctrl: true,
+            ..ModifierState::default()
+        };
+        let last = Some((Keysym::a, none, 1000));
+        assert!(!should_debounce(None, (Keysym::a, none, 1000), 50));
+        assert!(should_debounce(last, (Keysym::a, none, 1049), 50));
+        assert!(!should_debounce(last, (Keysym::a, none, 1050), 50));
+        assert!(!should_debounce(last, (Keysym::b, none, 1010), 50));
+        assert!(!should_debounce(last, (Keysym::a, ctrl, 1010), 50));
+        // The timestamps wrap around.
+        let last = Some((Keysym::a, none, u32::MAX - 10));
+        assert!(should_debounce(last, (Keysym::a, none, 10), 50));
+    }
+
+    #[test]
+    fn cursor_shape_changes() {
+        assert_eq!(cursor_shape_change(None, 1, true), Some(Shape::Pointer));
+        assert_eq!(cursor_shape_change(None, 1, false), Some(Shape::Default));
+        let hand = Some((Shape::Pointer, 1));
+        assert_eq!(cursor_shape_change(hand, 1, true), None);
+        assert_eq!(cursor_shape_change(hand, 1, false), Some(Shape::Default));
+        // A new enter event needs the shape to be set again.
+        assert_eq!(cursor_shape_change(hand, 2, true), Some(Shape::Pointer));
+    }
+
+    #[test]
+    fn uneven_border_sides() {
+        let config: config::Config = serde_yaml::from_str(
+            "border: '#ff0000'\nbackground: '#0000ff'\ncorner_r: 0\nborder_widths: { top: 0, right: 0, bottom: 0, left: 10 }\nmenu: []",
+        )
+        .unwrap();
+        let mut surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 40, 20).unwrap();
+        let cairo_ctx = cairo::Context::new(&surface).unwrap();
+        draw_uneven_border(
+            &cairo_ctx,
+            &config,
+            (40.0, 20.0),
+            config.border_widths(),
+            config.corner_radii(),
+        );
+        drop(cairo_ctx);
+        let stride = surface.stride() as usize;
+        let data = surface.data().unwrap();
+        // Pixels are stored as BGRA on little-endian machines.
+        let pixel = |x: usize, y: usize| &data[y * stride + x * 4..][..3];
+        assert_eq!(pixel(5, 10), [0, 0, 255]);
+        assert_eq!(pixel(20, 10), [255, 0, 0]);
+        assert_eq!(pixel(39, 0), [255, 0, 0]);
+    }
+
+    #[test]
+    fn daemon_hides_instead_of_exiting() {
+        let mut visibility = Visibility {
+            daemon: true,
+            hidden: false,
+        };
+        assert!(!visibility.show());
+        assert!(!visibility.close());
+        assert!(visibility.hidden);
+        assert!(visibility.show());
+        assert!(!visibility.hidden);
+        // Several signals while shown do nothing.
+        assert!(!visibility.show());
+
+        let mut visibility = Visibility {
+            daemon: false,
+            hidden: false,
+        };
+        assert!(visibility.close());
+        assert!(!visibility.hidden);
+    }
+
+    #[test]
+    fn parse_geometry() {
+        let geometry: Geometry = "300x200".parse().unwrap();
+        assert_eq!((geometry.width, geometry.height), (300, 200));
+        assert_eq!(geometry.position, None);
+        let geometry: Geometry = "300x200+10+-5".parse().unwrap();
+        assert_eq!(geometry.position, Some((10, -5)));
+        for invalid in ["300", "300x", "x200", "300x200+10", "axb"] {
+            assert!(invalid.parse::<Geometry>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn geometry_overrides_config() {
+        let mut config: config::Config = serde_yaml::from_str(
+            "anchor: bottom\noffset_y: 8\nmenu: [{ key: a, desc: A, cmd: a }]",
+        )
+        .unwrap();
+        let mut menu = menu::Menu::new(&config).unwrap();
+        let geometry: Geometry = "300x200+10+20".parse().unwrap();
+        geometry.apply(&mut config, &mut menu);
+        assert_eq!((menu.width(), menu.height()), (300.0, 200.0));
+        assert_eq!(
+            config.margins(config.anchor),
+            [20, config.margin_right, config.margin_bottom, 10]
+        );
+    }
+
+    #[test]
+    fn preview_release() {
+        let mut preview = Preview {
+            raw_code: 30,
+            page: 2,
+            parent: 0,
+            shown: false,
+        };
+        // Other keys do not end the preview.
+        assert_eq!(preview.released(31), None);
+        // A short press opens the submenu.
+        assert_eq!(preview.released(30), Some(2));
+        // Once previewed, releasing the key goes back.
+        preview.shown = true;
+        assert_eq!(preview.released(30), Some(0));
+    }
+
+    /// Creates Wayland objects without a compositor, for tests which only need their identity.
+    struct NoCompositor;
+
+    impl<I: Proxy> Dispatch<I, ()> for NoCompositor {
+        fn event(_: &mut Self, _: &I, _: I::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {}
+    }
+
+    #[test]
+    fn keyboard_per_seat() {
+        // Requests are only written to the socket, which nothing reads.
+        let (socket, _compositor) = std::os::unix::net::UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(socket).unwrap();
+        let queue = conn.new_event_queue::<NoCompositor>();
+        let qh = queue.handle();
+        let registry = conn.display().get_registry(&qh, ());
+        let seats: [WlSeat; 2] = [1, 2].map(|name| registry.bind(name, 7, &qh, ()));
+
+        let mut keyboards = HashMap::new();
+        for seat in &seats {
+            assert!(add_keyboard(&mut keyboards, seat, || seat.get_keyboard(&qh, ())));
+        }
+        assert!(!add_keyboard(&mut keyboards, &seats[0], || unreachable!()));
+        assert_eq!(keyboards.len(), 2);
+
+        remove_keyboard(&mut keyboards, &seats[0]);
+        assert!(!keyboards.contains_key(&seats[0]));
+        assert!(keyboards.contains_key(&seats[1]));
+        remove_keyboard(&mut keyboards, &seats[0]);
+        assert_eq!(keyboards.len(), 1);
+    }
+
+    #[test]
+    fn strict_initial_keys_requires_initial_keys() {
+        assert!(Args::try_parse_from(["wlr-which-key", "--strict-initial-keys"]).is_err());
+        let args =
+            Args::try_parse_from(["wlr-which-key", "--strict-initial-keys", "-k", "p"]).unwrap();
+        assert!(args.strict_initial_keys);
+    }
 }
@@ -1,45 +1,238 @@
+use std::cmp::Reverse;
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
 
-use anyhow::{Error, Result, bail};
+use anyhow::{Context, Error, Result, bail};
 use pangocairo::{cairo, pango};
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
 use smithay_client_toolkit::seat::keyboard::Keysym;
 
-use crate::DEBUG_LAYOUT;
 use crate::color::Color;
 use crate::config::{self, Config};
+use crate::debug_layout;
+use crate::dmenu;
 use crate::key::{Key, ModifierState, SingleKey};
 use crate::text::{self, ComputedText};
 
 pub struct Menu {
     pages: Vec<MenuPage>,
     cur_page: usize,
+    /// Columns of the currently visible items of the current page.
+    columns: Vec<MenuColumn>,
     separator: ComputedText,
+    context: pango::Context,
+    font: pango::FontDescription,
+    markup: bool,
+    modifier_style: config::ModifierStyle,
+    /// Shell and working directory for the commands of dynamic entries.
+    shell: Vec<String>,
+    cwd: Option<PathBuf>,
+    icon_size: f64,
+    rows_per_column: Option<usize>,
+    column_mode: config::Columns,
+    /// Number of tiles per row, if entries are shown as a grid.
+    grid_columns: Option<usize>,
+    /// Size of each tile of the grid, updated together with the layout.
+    tile_size: (f64, f64),
+    sibling_next_key: Option<Key>,
+    sibling_prev_key: Option<Key>,
+    sibling_wrap: bool,
+    search_key: Option<Key>,
+    quit_keys: Vec<SingleKey>,
+    search: Option<Search>,
+    search_fuzzy: bool,
+    keep_open_default: bool,
+    /// The item under the pointer.
+    hovered: Option<usize>,
+    /// Padding plus border width on the top, right, bottom and left sides of the surface.
+    inset: [f64; 4],
+    column_padding: f64,
+    row_spacing: f64,
+    key_desc_gap: f64,
+    max_height: Option<f64>,
+    max_width: Option<f64>,
+    /// Width and height the menu has whatever its contents, from `width_fraction` and
+    /// `height_fraction`.
+    fixed_size: (Option<f64>, Option<f64>),
+    /// Width and height the menu has at least, from `min_width` and `min_height`.
+    min_size: (Option<f64>, Option<f64>),
+    /// Offset of the contents, to center them horizontally in a menu enlarged to `min_size`, and
+    /// place them by `vertical_align` in a higher menu.
+    content_offset: (f64, f64),
+    vertical_align: config::VerticalAlign,
+    /// Number of rows shown at once, if the columns do not fit within `max_height`.
+    rows_per_view: Option<usize>,
+    /// Index of the slice of rows currently shown.
+    scroll: usize,
+    scroll_indicator: Option<ComputedText>,
+    /// Entries shown at most per page, from `max_entries`.
+    max_entries: Option<usize>,
+    /// Number of the entries left out because of `max_entries`.
+    overflow: Option<ComputedText>,
+    /// Receives the output of dynamic entries run in the background.
+    dynamic_sender: Option<Sender<DynamicOutput>>,
+    /// Output of the last command run with `show_output`.
+    status: Option<ComputedText>,
+    /// The configured `footer`, always shown last.
+    hint: Option<ComputedText>,
+    /// Size of the surface, updated together with the layout.
+    size: (f64, f64),
+    /// Bounding boxes of the shown items, updated together with the layout.
+    entry_rects: Vec<(usize, Rect)>,
 }
 
 struct MenuPage {
     item_height: f64,
-    columns: Vec<MenuColumn>,
+    items: Vec<MenuItem>,
     parent: Option<usize>,
+    /// Descriptions of the submenus leading to this page.
+    breadcrumb: Option<ComputedText>,
+    title: Option<ComputedText>,
+    /// Overrides the global anchor for this page and the pages below it.
+    anchor: Option<config::ConfigAnchor>,
+    /// Items generated by the commands of dynamic entries.
+    dynamic: Vec<DynamicItems>,
+}
+
+struct DynamicItems {
+    cmd: String,
+    /// Path of the page, extended by the key of each item for its `Exec` action.
+    path: Vec<String>,
+    /// Position of the generated items among the items of the page.
+    range: Range<usize>,
+    /// Run `cmd` again each time the page is opened.
+    refresh: bool,
+    /// Identifies the last run of `cmd`, so that the output of older runs is ignored.
+    run_id: u64,
+}
+
+/// Output of the command of a dynamic entry, run in the background.
+pub struct DynamicOutput {
+    page: usize,
+    /// Index of the dynamic entry among those of the page.
+    index: usize,
+    run_id: u64,
+    entries: Result<Vec<config::Entry>>,
 }
 
+/// Unique among all menus, since the output of a run may arrive after the config is reloaded.
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Columns are sized to their own items, so that a column of short entries stays narrow even if
+/// another one has long descriptions.
 struct MenuColumn {
+    /// Zero if no item in this column has an icon.
+    icon_col_width: f64,
     key_col_width: f64,
     val_col_width: f64,
-    items: Vec<MenuItem>,
+    /// Indices into the items of the current page.
+    items: Vec<usize>,
 }
 
 struct MenuItem {
-    action: Action,
+    kind: ItemKind,
     key_comp: ComputedText,
     val_comp: ComputedText,
-    key: Key,
+    desc: String,
+    color: Option<Color>,
+    icon: Option<cairo::ImageSurface>,
+}
+
+enum ItemKind {
+    Entry {
+        key: Key,
+        /// `key` as it is on the active keyboard layout, shown and matched instead of it.
+        layout_key: Key,
+        action: Action,
+    },
+    /// A divider line, with the label (if any) in `val_comp`.
+    Separator,
+    /// A row of text which cannot be selected, such as an error.
+    Message,
+}
+
+impl ItemKind {
+    fn entry(key: &Key, action: Action) -> Self {
+        Self::Entry {
+            key: key.clone(),
+            layout_key: key.clone(),
+            action,
+        }
+    }
+}
+
+impl MenuPage {
+    /// Whether the first item with a strong text direction is right-to-left.
+    fn is_rtl(&self) -> bool {
+        self.items.iter().find_map(|item| {
+            match pango::find_base_dir(&item.val_comp.layout.text()) {
+                pango::Direction::Rtl => Some(true),
+                pango::Direction::Ltr => Some(false),
+                _ => None,
+            }
+        }) == Some(true)
+    }
+}
+
+impl MenuItem {
+    fn action(&self) -> Option<&Action> {
+        match &self.kind {
+            ItemKind::Entry { action, .. } => Some(action),
+            ItemKind::Separator | ItemKind::Message => None,
+        }
+    }
+
+    fn is_separator(&self) -> bool {
+        matches!(self.kind, ItemKind::Separator)
+    }
+}
+
+/// A rectangle in surface-local coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+struct Search {
+    query: String,
+    prompt: ComputedText,
 }
 
 #[derive(Clone)]
 pub enum Action {
     Quit,
-    Exec { cmd: String, keep_open: bool },
+    Exec {
+        cmd: Vec<String>,
+        keep_open: bool,
+        keep_going: bool,
+        show_output: bool,
+        repeat: bool,
+        desc: String,
+        path: Vec<String>,
+        /// Page opened after running the command.
+        submenu: Option<usize>,
+    },
     Submenu(usize),
+    /// Show the page asking to confirm a command.
+    Confirm(usize),
+    Search,
+    /// Show another slice of rows of a menu taller than the screen.
+    Scroll(isize),
+    Reload,
+    Copy(String),
 }
 
 impl Menu {
@@ -51,10 +244,64 @@ impl Menu {
         let mut this = Self {
             pages: Vec::new(),
             cur_page: 0,
+            columns: Vec::new(),
             separator: ComputedText::new(&config.separator, &context, &config.font.0),
+            context: context.clone(),
+            font: config.font.0.clone(),
+            markup: config.markup,
+            modifier_style: config.modifier_style,
+            shell: config.shell.clone(),
+            cwd: config.cwd.clone(),
+            icon_size: config.icon_size,
+            rows_per_column: config.rows_per_column,
+            column_mode: config.columns,
+            grid_columns: match config.layout {
+                config::MenuLayout::List => None,
+                config::MenuLayout::Grid => Some(config.grid_columns),
+            },
+            tile_size: (0.0, 0.0),
+            sibling_next_key: config.sibling_next_key.clone(),
+            sibling_prev_key: config.sibling_prev_key.clone(),
+            sibling_wrap: config.sibling_wrap,
+            search_key: config.search_key.clone(),
+            quit_keys: config.quit_keys.clone(),
+            search: None,
+            search_fuzzy: config.search_fuzzy,
+            keep_open_default: config.keep_open_default,
+            hovered: None,
+            inset: config.insets(),
+            column_padding: config.column_padding(),
+            row_spacing: config.row_spacing,
+            key_desc_gap: config.key_desc_gap,
+            max_height: config.max_height.map(f64::from),
+            max_width: config.max_width.map(f64::from),
+            fixed_size: (None, None),
+            min_size: (
+                config.min_width.map(f64::from),
+                config.min_height.map(f64::from),
+            ),
+            content_offset: (0.0, 0.0),
+            vertical_align: config.vertical_align,
+            rows_per_view: None,
+            scroll: 0,
+            scroll_indicator: None,
+            max_entries: config.max_entries,
+            overflow: None,
+            dynamic_sender: None,
+            status: None,
+            hint: config.footer.as_ref().map(|footer| {
+                let text = match footer.as_str() {
+                    AUTO_FOOTER => auto_footer(config),
+                    text => text.to_owned(),
+                };
+                ComputedText::new(text, &context, &config.font.0)
+            }),
+            size: (0.0, 0.0),
+            entry_rects: Vec::new(),
         };
 
-        this.push_page(&context, &config.menu, config, None)?;
+        this.push_page(&context, &config.menu, config, None, Vec::new(), Vec::new())?;
+        this.update_layout();
 
         Ok(this)
     }
@@ -65,6 +312,8 @@ impl Menu {
         entries: &[config::Entry],
         config: &Config,
         parent: Option<usize>,
+        path: Vec<String>,
+        descs: Vec<String>,
     ) -> Result<usize> {
         if entries.is_empty() {
             bail!("Empty menu pages are not allowed");
@@ -72,103 +321,913 @@ impl Menu {
 
         let cur_page = self.pages.len();
 
+        let breadcrumb = (config.show_breadcrumb && !descs.is_empty())
+            .then(|| ComputedText::new(descs.join(&config.separator), context, &config.font.0));
         self.pages.push(MenuPage {
             item_height: self.separator.height,
-            columns: Vec::new(),
+            items: Vec::new(),
             parent,
+            breadcrumb,
+            title: None,
+            anchor: None,
+            dynamic: Vec::new(),
         });
 
-        for (entry_i, entry) in entries.iter().enumerate() {
-            let item = match entry {
+        for entry in entries {
+            let item_path = |key: &Key| {
+                let mut path = path.clone();
+                path.push(key.primary_repr().to_owned());
+                path
+            };
+            let (kind, label) = match entry {
                 config::Entry::Cmd {
                     key,
                     cmd,
                     desc,
                     keep_open,
-                } => MenuItem {
-                    action: Action::Exec {
-                        cmd: cmd.into(),
-                        keep_open: *keep_open,
-                    },
-                    key_comp: ComputedText::new(key.to_string(), context, &config.font.0),
-                    val_comp: ComputedText::new(desc, context, &config.font.0),
-                    key: key.clone(),
-                },
+                    keep_going,
+                    confirm,
+                    show_output,
+                    repeat,
+                    ..
+                } => {
+                    let mut action = Action::Exec {
+                        cmd: cmd.clone(),
+                        keep_open: keep_open.unwrap_or(config.keep_open_default),
+                        keep_going: *keep_going,
+                        show_output: *show_output,
+                        repeat: *repeat,
+                        desc: desc.into(),
+                        path: item_path(key),
+                        submenu: None,
+                    };
+                    if let Some(prompt) = confirm {
+                        action = Action::Confirm(self.push_confirm_page(cur_page, prompt, action));
+                    }
+                    (ItemKind::entry(key, action), desc.clone())
+                }
                 config::Entry::Recursive {
                     key,
                     submenu: entries,
+                    cmd,
                     desc,
+                    title,
+                    anchor,
+                    ..
                 } => {
-                    let new_page = self.push_page(context, entries, config, Some(cur_page))?;
-                    MenuItem {
-                        action: Action::Submenu(new_page),
-                        key_comp: ComputedText::new(key.to_string(), context, &config.font.0),
-                        val_comp: ComputedText::new(format!("+{desc}"), context, &config.font.0),
-                        key: key.clone(),
+                    let mut descs = descs.clone();
+                    descs.push(desc.clone());
+                    let new_page = self.push_page(
+                        context,
+                        entries,
+                        config,
+                        Some(cur_page),
+                        item_path(key),
+                        descs,
+                    )?;
+                    if let Some(title) = title {
+                        let mut font = config.font.0.clone();
+                        font.set_weight(pango::Weight::Bold);
+                        self.pages[new_page].title = Some(ComputedText::new(title, context, &font));
                     }
+                    self.pages[new_page].anchor = *anchor;
+                    let action = match cmd {
+                        Some(cmd) => Action::Exec {
+                            cmd: cmd.clone(),
+                            keep_open: true,
+                            keep_going: false,
+                            show_output: false,
+                            repeat: false,
+                            desc: desc.into(),
+                            path: item_path(key),
+                            submenu: Some(new_page),
+                        },
+                        None => Action::Submenu(new_page),
+                    };
+                    (ItemKind::entry(key, action), format!("+{desc}"))
+                }
+                config::Entry::Reload { key, desc, .. } => {
+                    let action = Action::Reload;
+                    (ItemKind::entry(key, action), desc.clone())
+                }
+                config::Entry::Copy {
+                    key, text, desc, ..
+                } => {
+                    let action = Action::Copy(text.clone());
+                    (ItemKind::entry(key, action), desc.clone())
                 }
+                config::Entry::Separator { label } => {
+                    (ItemKind::Separator, label.clone().unwrap_or_default())
+                }
+                config::Entry::Dynamic { cmd, refresh } => {
+                    // Run by `set_dynamic_sender`, so that building the menu does not wait for
+                    // the command.
+                    let start = self.pages[cur_page].items.len();
+                    let item = self.loading_item();
+                    self.push_item(cur_page, item);
+                    self.pages[cur_page].dynamic.push(DynamicItems {
+                        cmd: cmd.clone(),
+                        path: path.clone(),
+                        range: start..start + 1,
+                        refresh: *refresh,
+                        run_id: NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed),
+                    });
+                    continue;
+                }
+                config::Entry::Include { .. } => {
+                    unreachable!("includes are resolved when the config is loaded")
+                }
+            };
+            let item = self.new_item(entry, kind, &label);
+            self.push_item(cur_page, item);
+        }
+
+        Ok(cur_page)
+    }
+
+    /// Push a page with `prompt` as its title, running `action` on `y` and going back to `parent`
+    /// on `n`.
+    fn push_confirm_page(&mut self, parent: usize, prompt: &str, action: Action) -> usize {
+        let page = self.pages.len();
+        let mut title_font = self.font.clone();
+        title_font.set_weight(pango::Weight::Bold);
+        self.pages.push(MenuPage {
+            item_height: self.separator.height,
+            items: Vec::new(),
+            parent: Some(parent),
+            breadcrumb: None,
+            title: Some(ComputedText::new(prompt, &self.context, &title_font)),
+            anchor: None,
+            dynamic: Vec::new(),
+        });
+        for (key, desc, action) in [("y", "Yes", action), ("n", "No", Action::Submenu(parent))] {
+            let key = Key::from(SingleKey::from_str(key).expect("valid key"));
+            let item = MenuItem {
+                key_comp: ComputedText::new(key.to_string(), &self.context, &self.font),
+                val_comp: ComputedText::new(desc, &self.context, &self.font),
+                kind: ItemKind::entry(&key, action),
+                desc: desc.into(),
+                color: None,
+                icon: None,
             };
+            self.push_item(page, item);
+        }
+        page
+    }
+
+    /// The item showing `entry`, with `label` as its description.
+    fn new_item(&self, entry: &config::Entry, kind: ItemKind, label: &str) -> MenuItem {
+        let font = entry.font().map_or(&self.font, |font| &font.0);
+        let key_text = entry.key().map(Key::to_string).unwrap_or_default();
+        let val_comp = match self.markup {
+            true => ComputedText::new_markup(label, &self.context, font),
+            false => ComputedText::new(label, &self.context, font),
+        };
+        let mut key_comp = ComputedText::new(key_text, &self.context, font);
+        if let Some(key) = entry.key() {
+            style_modifiers(self.modifier_style, &mut key_comp, key);
+        }
+        MenuItem {
+            kind,
+            key_comp,
+            val_comp,
+            desc: entry.desc().to_owned(),
+            color: entry.color(),
+            icon: entry.icon().and_then(load_icon),
+        }
+    }
+
+    fn push_item(&mut self, page: usize, item: MenuItem) {
+        if !item.is_separator() {
+            let height = self.entry_height(&item);
+            let page = &mut self.pages[page];
+            page.item_height = page.item_height.max(height);
+        }
+        self.pages[page].items.push(item);
+    }
+
+    /// Height needed by a row which is not a separator.
+    fn entry_height(&self, item: &MenuItem) -> f64 {
+        let height = f64::max(item.key_comp.height, item.val_comp.height);
+        match item.icon {
+            Some(_) => height.max(self.icon_size),
+            None => height,
+        }
+    }
+
+    /// A row showing `text` which cannot be selected.
+    fn message_item(&self, text: String) -> MenuItem {
+        MenuItem {
+            kind: ItemKind::Message,
+            key_comp: ComputedText::new("", &self.context, &self.font),
+            val_comp: ComputedText::new(&text, &self.context, &self.font),
+            desc: text,
+            color: None,
+            icon: None,
+        }
+    }
+
+    /// The row shown in place of the entries of a dynamic entry while its command runs.
+    fn loading_item(&self) -> MenuItem {
+        self.message_item("Loading…".to_owned())
+    }
+
+    /// Items for the entries printed by `cmd`, or a message if it fails.
+    fn dynamic_items(&self, cmd: &str, path: &[String]) -> Vec<MenuItem> {
+        let entries = dmenu::run_entries(cmd, &self.shell, self.cwd.as_deref());
+        self.output_items(entries, path)
+    }
+
+    /// Items for the entries read from the output of a dynamic entry, or a message if its
+    /// command failed.
+    fn output_items(&self, entries: Result<Vec<config::Entry>>, path: &[String]) -> Vec<MenuItem> {
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(err) => {
+                let text = format!("{err:#}");
+                eprintln!("Warning: {text}");
+                return vec![self.message_item(text)];
+            }
+        };
+        entries
+            .iter()
+            .map(|entry| {
+                let config::Entry::Cmd { key, cmd, desc, .. } = entry else {
+                    unreachable!("dynamic entries are always commands")
+                };
+                let mut path = path.to_vec();
+                path.push(key.primary_repr().to_owned());
+                let action = Action::Exec {
+                    cmd: cmd.clone(),
+                    keep_open: self.keep_open_default,
+                    keep_going: false,
+                    show_output: false,
+                    repeat: false,
+                    desc: desc.clone(),
+                    path,
+                    submenu: None,
+                };
+                self.new_item(entry, ItemKind::entry(key, action), desc)
+            })
+            .collect()
+    }
+
+    /// Run the commands of the refreshing dynamic entries of the current page again.
+    fn refresh_dynamic(&mut self) {
+        let page_i = self.cur_page;
+        for index in 0..self.pages[page_i].dynamic.len() {
+            if self.pages[page_i].dynamic[index].refresh {
+                self.run_dynamic(page_i, index);
+            }
+        }
+    }
+
+    /// Run the command of the dynamic entry at `index` of `page_i`. With a sender from
+    /// `set_dynamic_sender`, the command runs in the background and a loading message is shown
+    /// until its output is passed to `finish_dynamic`.
+    fn run_dynamic(&mut self, page_i: usize, index: usize) {
+        let run_id = NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed);
+        self.pages[page_i].dynamic[index].run_id = run_id;
+        let dynamic = &self.pages[page_i].dynamic[index];
+        let items = match &self.dynamic_sender {
+            Some(sender) => {
+                let sender = sender.clone();
+                let cmd = dynamic.cmd.clone();
+                let shell = self.shell.clone();
+                let cwd = self.cwd.clone();
+                thread::spawn(move || {
+                    let entries = dmenu::run_entries(&cmd, &shell, cwd.as_deref());
+                    // The menu is gone if the receiver is.
+                    let _ = sender.send(DynamicOutput {
+                        page: page_i,
+                        index,
+                        run_id,
+                        entries,
+                    });
+                });
+                vec![self.loading_item()]
+            }
+            None => self.dynamic_items(&dynamic.cmd, &dynamic.path),
+        };
+        self.replace_dynamic_items(page_i, index, items);
+    }
+
+    /// Run the commands of all dynamic entries in the background, now and whenever they are
+    /// refreshed, sending their output to `sender`. Until then, the entries are shown as loading.
+    pub fn set_dynamic_sender(&mut self, sender: Sender<DynamicOutput>) {
+        self.dynamic_sender = Some(sender);
+        for page_i in 0..self.pages.len() {
+            for index in 0..self.pages[page_i].dynamic.len() {
+                self.run_dynamic(page_i, index);
+            }
+        }
+        self.update_layout();
+    }
+
+    /// Show the entries read from the output of a command run in the background, unless it was
+    /// run again since. Returns whether the current page changed.
+    pub fn finish_dynamic(&mut self, output: DynamicOutput) -> bool {
+        let Some(dynamic) = self
+            .pages
+            .get(output.page)
+            .and_then(|page| page.dynamic.get(output.index))
+            .filter(|dynamic| dynamic.run_id == output.run_id)
+        else {
+            return false;
+        };
+        let path = dynamic.path.clone();
+        let items = self.output_items(output.entries, &path);
+        self.replace_dynamic_items(output.page, output.index, items);
+        if output.page != self.cur_page {
+            return false;
+        }
+        self.update_layout();
+        true
+    }
+
+    /// Replace the items of the dynamic entry at `index` of `page_i`.
+    fn replace_dynamic_items(&mut self, page_i: usize, index: usize, items: Vec<MenuItem>) {
+        let page = &mut self.pages[page_i];
+        let range = page.dynamic[index].range.clone();
+        let added = items.len();
+        page.items.splice(range.clone(), items);
+        page.dynamic[index].range = range.start..range.start + added;
+        // The items of the following dynamic entries moved.
+        let shift = added as isize - range.len() as isize;
+        for later in &mut page.dynamic[index + 1..] {
+            later.range = later.range.start.saturating_add_signed(shift)
+                ..later.range.end.saturating_add_signed(shift);
+        }
+
+        let page = &self.pages[page_i];
+        let item_height = page
+            .items
+            .iter()
+            .filter(|item| !item.is_separator())
+            .map(|item| self.entry_height(item))
+            .fold(self.separator.height, f64::max);
+        self.pages[page_i].item_height = item_height;
+    }
+
+    /// Items of the current page which match the search query, if any. Only selectable items are
+    /// shown while searching, best fuzzy matches first.
+    fn visible_items(&self) -> impl Iterator<Item = (usize, &MenuItem)> {
+        self.limited_items().0.into_iter()
+    }
+
+    /// The items of `matching_items` up to `max_entries` entries, and the number of entries left
+    /// out.
+    fn limited_items(&self) -> (Vec<(usize, &MenuItem)>, usize) {
+        let mut items: Vec<_> = self.matching_items().collect();
+        let Some(max_entries) = self.max_entries else {
+            return (items, 0);
+        };
+        let mut entries = items
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, item))| item.action().is_some());
+        let Some((cut, _)) = entries.nth(max_entries) else {
+            return (items, 0);
+        };
+        let hidden = entries.count() + 1;
+        items.truncate(cut);
+        (items, hidden)
+    }
+
+    /// The items of the current page matching the search query, if any, best match first.
+    fn matching_items(&self) -> impl Iterator<Item = (usize, &MenuItem)> {
+        let items = self.pages[self.cur_page].items.iter().enumerate();
+        let Some(search) = &self.search else {
+            return items.collect::<Vec<_>>().into_iter();
+        };
+        let query = search.query.to_lowercase();
+        let mut matches: Vec<_> = items
+            .filter(|(_, item)| item.action().is_some())
+            .filter_map(|(item_i, item)| {
+                let score = match self.search_fuzzy {
+                    true => fuzzy_match(&query, &item.desc)?.0,
+                    false => item.desc.to_lowercase().contains(&query).then_some(0)?,
+                };
+                Some((score, item_i, item))
+            })
+            .collect();
+        // The sort is stable, so equally good matches stay in config order.
+        matches.sort_by_key(|&(score, ..)| Reverse(score));
+        matches
+            .into_iter()
+            .map(|(_, item_i, item)| (item_i, item))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Height of the row of `item` on the current page. Separators without a label take half
+    /// the height of an entry.
+    fn row_height(&self, item: &MenuItem) -> f64 {
+        if self.grid_columns.is_some() {
+            return self.tile_size.1;
+        }
+        let item_height = self.pages[self.cur_page].item_height;
+        match item.kind {
+            ItemKind::Separator if item.desc.is_empty() => item_height * 0.5,
+            ItemKind::Separator => item.val_comp.height,
+            ItemKind::Entry { .. } | ItemKind::Message => item_height,
+        }
+    }
+
+    /// Height of the shown rows of the tallest column.
+    fn rows_height(&self) -> f64 {
+        let items = &self.pages[self.cur_page].items;
+        self.columns
+            .iter()
+            .map(|col| {
+                let rows = self.column_slice(col);
+                rows.iter()
+                    .map(|&item_i| self.row_height(&items[item_i]))
+                    .sum::<f64>()
+                    + rows.len().saturating_sub(1) as f64 * self.row_spacing
+            })
+            .fold(0.0, f64::max)
+    }
 
-            let height = f64::max(item.key_comp.height, item.val_comp.height);
-            if height > self.pages[cur_page].item_height {
-                self.pages[cur_page].item_height = height;
+    /// Arrange the visible items into columns. Must be called whenever the page or the search
+    /// query changes. Text is measured once when the items are created, so this only has to
+    /// combine the cached extents.
+    fn update_layout(&mut self) {
+        self.columns = match self.grid_columns {
+            Some(grid_columns) => {
+                let (columns, tile_size) = self.grid_layout(grid_columns);
+                self.tile_size = tile_size;
+                columns
             }
+            None => self.list_layout(),
+        };
+        let hidden = self.limited_items().1;
+        self.overflow = (hidden > 0)
+            .then(|| ComputedText::new(format!("… {hidden} more"), &self.context, &self.font));
+        self.fit_max_width();
+        self.hovered = None;
+        self.scroll = 0;
+        self.update_view();
+    }
 
-            let col_i = config
-                .rows_per_column
-                .map_or(0, |rows_per_column| entry_i / rows_per_column);
+    fn list_layout(&self) -> Vec<MenuColumn> {
+        let rows_per_column = self.rows_per_column.map(|max_rows| match self.column_mode {
+            config::Columns::Fill => max_rows,
+            config::Columns::Auto => {
+                let items = self.visible_items().count();
+                items.div_ceil(items.div_ceil(max_rows).max(1))
+            }
+        });
+        let mut columns: Vec<MenuColumn> = Vec::new();
+        for (row_i, (item_i, item)) in self.visible_items().enumerate() {
+            let col_i = rows_per_column.map_or(0, |rows_per_column| row_i / rows_per_column);
+            let icon_col_width = match item.icon {
+                Some(_) => self.icon_size + ICON_SPACING,
+                None => 0.0,
+            };
 
-            if col_i == self.pages[cur_page].columns.len() {
-                self.pages[cur_page].columns.push(MenuColumn {
+            if col_i == columns.len() {
+                columns.push(MenuColumn {
+                    icon_col_width,
                     key_col_width: item.key_comp.width,
                     val_col_width: item.val_comp.width,
-                    items: vec![item],
+                    items: vec![item_i],
                 });
             } else {
-                let col = &mut self.pages[cur_page].columns[col_i];
+                let col = &mut columns[col_i];
+                col.icon_col_width = col.icon_col_width.max(icon_col_width);
                 col.key_col_width = col.key_col_width.max(item.key_comp.width);
                 col.val_col_width = col.val_col_width.max(item.val_comp.width);
-                col.items.push(item);
+                col.items.push(item_i);
             }
         }
+        columns
+    }
 
-        Ok(cur_page)
+    /// Columns of tiles, filled row by row, and the size of the tiles, which fit the widest
+    /// description.
+    fn grid_layout(&self, grid_columns: usize) -> (Vec<MenuColumn>, (f64, f64)) {
+        let mut columns: Vec<MenuColumn> = Vec::new();
+        let mut content_width: f64 = 0.0;
+        let mut has_icon = false;
+        let visible = self
+            .visible_items()
+            .filter(|(_, item)| !item.is_separator());
+        for (tile_i, (item_i, item)) in visible.enumerate() {
+            let col_i = tile_i % grid_columns;
+            if col_i == columns.len() {
+                columns.push(MenuColumn {
+                    icon_col_width: 0.0,
+                    key_col_width: 0.0,
+                    val_col_width: 0.0,
+                    items: Vec::new(),
+                });
+            }
+            columns[col_i].items.push(item_i);
+            content_width = content_width
+                .max(item.val_comp.width)
+                .max(item.key_comp.width);
+            has_icon |= item.icon.is_some();
+        }
+        let item_height = self.pages[self.cur_page].item_height;
+        let icon_height = match has_icon {
+            true => self.icon_size + ICON_SPACING,
+            false => 0.0,
+        };
+        // The key line, then the icon and the description.
+        let tile_size = (
+            content_width.max(self.icon_size) + TILE_PADDING * 2.0,
+            item_height * 2.0 + icon_height + TILE_PADDING * 2.0,
+        );
+        (columns, tile_size)
     }
 
-    pub fn width(&self, config: &Config) -> f64 {
-        let page = &self.pages[self.cur_page];
-        page.columns
+    /// Ellipsize the descriptions in each column to an equal share of `max_width`, if the columns
+    /// do not fit otherwise. Keys are never cut.
+    fn fit_max_width(&mut self) {
+        let Some(max_width) = self
+            .max_width
+            .filter(|_| !self.columns.is_empty() && self.grid_columns.is_none())
+        else {
+            return;
+        };
+        let available = max_width - self.inset[1] - self.inset[3];
+        let max_val_width = (self.columns_width() > available).then(|| {
+            let fixed_width: f64 = self
+                .columns
+                .iter()
+                .map(|col| col.icon_col_width + col.key_col_width + self.key_desc_width())
+                .sum::<f64>()
+                + (self.columns.len() - 1) as f64 * self.column_padding;
+            ((available - fixed_width) / self.columns.len() as f64).max(0.0)
+        });
+
+        let items = &mut self.pages[self.cur_page].items;
+        for col in &mut self.columns {
+            col.val_col_width = 0.0;
+            for &item_i in &col.items {
+                let val_comp = &mut items[item_i].val_comp;
+                val_comp.set_max_width(max_val_width);
+                col.val_col_width = col.val_col_width.max(val_comp.width);
+            }
+        }
+    }
+
+    /// Set the height the surface must fit in, unless one was configured.
+    pub fn set_output_height(&mut self, height: f64) {
+        self.max_height = Some(height);
+        self.update_view();
+    }
+
+    /// Give the menu a fixed width and height, for those which are `Some`. Descriptions are cut
+    /// and rows split into pages to fit.
+    pub fn set_fixed_size(&mut self, (width, height): (Option<f64>, Option<f64>)) {
+        self.fixed_size = (width, height);
+        self.max_width = width.or(self.max_width);
+        self.max_height = height.or(self.max_height);
+        self.update_layout();
+    }
+
+    fn update_view(&mut self) {
+        self.paginate();
+        self.size = (self.compute_width(), self.compute_height());
+        let spare_height = (self.size.1 - self.natural_height()).max(0.0);
+        self.content_offset = (
+            match self.fixed_size.0 {
+                Some(_) => 0.0,
+                None => ((self.size.0 - self.natural_width()) * 0.5).max(0.0),
+            },
+            match self.vertical_align {
+                config::VerticalAlign::Top => 0.0,
+                config::VerticalAlign::Center => spare_height * 0.5,
+                config::VerticalAlign::Bottom => spare_height,
+            },
+        );
+        self.entry_rects = self.compute_entry_rects();
+    }
+
+    /// Split the rows into slices if the columns are taller than `max_height`.
+    fn paginate(&mut self) {
+        self.rows_per_view = None;
+        self.scroll_indicator = None;
+        let Some(max_height) = self.max_height else {
+            return;
+        };
+        let item_height = self.pages[self.cur_page].item_height;
+        let rows = self.rows();
+        let available = max_height
+            - self.inset[0]
+            - self.inset[2]
+            - self.header_height()
+            - self.footer_height();
+        if self.rows_height() <= available {
+            return;
+        }
+
+        // One row is taken by the page indicator.
+        let row_height = match self.grid_columns {
+            Some(_) => self.tile_size.1,
+            None => item_height,
+        };
+        let rows_per_view = (((available - item_height + self.row_spacing)
+            / (row_height + self.row_spacing))
+            .floor() as usize)
+            .max(1);
+        let pages = rows.div_ceil(rows_per_view);
+        self.scroll = self.scroll.min(pages.saturating_sub(1));
+        self.rows_per_view = Some(rows_per_view);
+        self.scroll_indicator = Some(ComputedText::new(
+            format!("{}/{pages}", self.scroll + 1),
+            &self.context,
+            &self.font,
+        ));
+    }
+
+    /// Show the next (`delta > 0`) or previous slice of rows. Returns whether anything changed.
+    pub fn scroll_by(&mut self, delta: isize) -> bool {
+        let Some(rows_per_view) = self.rows_per_view else {
+            return false;
+        };
+        let pages = self.rows().div_ceil(rows_per_view);
+        let scroll = self
+            .scroll
+            .saturating_add_signed(delta)
+            .min(pages.saturating_sub(1));
+        if scroll == self.scroll {
+            return false;
+        }
+        self.scroll = scroll;
+        self.hovered = None;
+        self.update_view();
+        true
+    }
+
+    /// Number of rows in the tallest column.
+    fn rows(&self) -> usize {
+        self.columns
             .iter()
-            .map(|col| col.key_col_width + col.val_col_width + self.separator.width)
-            .sum::<f64>()
-            + (page.columns.len() - 1) as f64 * config.column_padding()
-            + (config.padding() + config.border_width) * 2.0
+            .map(|col| col.items.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Items of `col` in the currently shown slice of rows.
+    fn column_slice<'a>(&self, col: &'a MenuColumn) -> &'a [usize] {
+        match self.rows_per_view {
+            Some(rows_per_view) => {
+                let start = (self.scroll * rows_per_view).min(col.items.len());
+                let end = (start + rows_per_view).min(col.items.len());
+                &col.items[start..end]
+            }
+            None => &col.items,
+        }
     }
 
-    pub fn height(&self, config: &Config) -> f64 {
+    /// Lines drawn above the entries.
+    fn header(&self) -> impl Iterator<Item = &ComputedText> {
         let page = &self.pages[self.cur_page];
-        page.columns
+        page.breadcrumb
+            .iter()
+            .chain(&page.title)
+            .chain(self.search.as_ref().map(|s| &s.prompt))
+    }
+
+    fn header_height(&self) -> f64 {
+        let item_height = self.pages[self.cur_page].item_height;
+        self.header().map(|line| line.height.max(item_height)).sum()
+    }
+
+    /// Lines drawn below the entries.
+    fn footer(&self) -> impl Iterator<Item = &ComputedText> {
+        self.overflow
+            .iter()
+            .chain(&self.scroll_indicator)
+            .chain(&self.status)
+            .chain(&self.hint)
+    }
+
+    /// Show `text` below the entries until it is replaced. Nothing is shown if it is empty.
+    pub fn set_status(&mut self, text: &str) {
+        self.status =
+            (!text.is_empty()).then(|| ComputedText::new(text, &self.context, &self.font));
+        self.update_view();
+    }
+
+    fn footer_height(&self) -> f64 {
+        let item_height = self.pages[self.cur_page].item_height;
+        self.footer().map(|line| line.height.max(item_height)).sum()
+    }
+
+    pub fn width(&self) -> f64 {
+        self.size.0
+    }
+
+    pub fn height(&self) -> f64 {
+        self.size.1
+    }
+
+    /// Width of the columns side by side.
+    fn columns_width(&self) -> f64 {
+        self.columns
             .iter()
-            .map(|col| page.item_height * col.items.len() as f64)
-            .max_by(f64::total_cmp)
-            .unwrap()
-            + (config.padding() + config.border_width) * 2.0
+            .map(|col| self.column_width(col))
+            .sum::<f64>()
+            + self.columns.len().saturating_sub(1) as f64 * self.column_padding
+    }
+
+    /// Width of the contents and insets, whatever the size limits.
+    fn natural_width(&self) -> f64 {
+        let columns_width = self.columns_width();
+        let header_width = self
+            .header()
+            .chain(self.footer())
+            .map(|line| line.width)
+            .fold(0.0, f64::max);
+        columns_width.max(header_width) + self.inset[1] + self.inset[3]
+    }
+
+    /// Height of the contents and insets, whatever the size limits.
+    fn natural_height(&self) -> f64 {
+        self.rows_height()
+            + self.header_height()
+            + self.footer_height()
+            + self.inset[0]
+            + self.inset[2]
+    }
+
+    fn compute_width(&self) -> f64 {
+        if let Some(width) = self.fixed_size.0 {
+            return width;
+        }
+        let width = self.natural_width().max(self.min_size.0.unwrap_or(0.0));
+        match self.max_width {
+            Some(max_width) => width.min(max_width),
+            None => width,
+        }
+    }
+
+    fn compute_height(&self) -> f64 {
+        if let Some(height) = self.fixed_size.1 {
+            return height;
+        }
+        let height = self.natural_height().max(self.min_size.1.unwrap_or(0.0));
+        match self.max_height {
+            Some(max_height) => height.min(max_height),
+            None => height,
+        }
     }
 
     pub fn render(&self, config: &config::Config, cairo_ctx: &cairo::Context) -> Result<()> {
-        let mut dx = config.padding() + config.border_width;
-        let dy = config.padding() + config.border_width;
+        let [top, _, _, left] = self.inset;
+        let (mut dx, mut dy) = (left + self.content_offset.0, top + self.content_offset.1);
         let page = &self.pages[self.cur_page];
-        for col in &page.columns {
+        for line in self.header() {
+            let height = line.height.max(page.item_height);
+            line.render(
+                cairo_ctx,
+                text::RenderOptions {
+                    x: dx,
+                    y: dy,
+                    fg_color: config.color,
+                    height,
+                },
+            )?;
+            dy += height;
+        }
+        let footer_y = dy + self.rows_height();
+        for col in &self.columns {
             self.render_column(config, cairo_ctx, dx, dy, page, col)?;
-            dx += col.key_col_width
-                + col.val_col_width
-                + self.separator.width
-                + config.column_padding();
+            dx += self.column_width(col) + config.column_padding();
+        }
+        dy = footer_y;
+        for line in self.footer() {
+            let height = line.height.max(page.item_height);
+            let is_hint = self
+                .hint
+                .as_ref()
+                .is_some_and(|hint| std::ptr::eq(hint, line));
+            line.render(
+                cairo_ctx,
+                text::RenderOptions {
+                    x: left + self.content_offset.0,
+                    y: dy,
+                    fg_color: match is_hint {
+                        true => config.color.scale_alpha(HINT_ALPHA),
+                        false => config.color,
+                    },
+                    height,
+                },
+            )?;
+            dy += height;
+        }
+        if debug_layout() {
+            self.render_debug_overlay(cairo_ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Shade the area of each entry and outline the space inside the padding and border.
+    fn render_debug_overlay(&self, cairo_ctx: &cairo::Context) -> Result<()> {
+        Color::from_rgba(0, 255, 0, 64).apply(cairo_ctx);
+        for (_, rect) in self.entry_rects() {
+            cairo_ctx.rectangle(rect.x, rect.y, rect.width, rect.height);
         }
+        cairo_ctx.fill()?;
+        let [top, right, bottom, left] = self.inset;
+        Color::from_rgba(255, 0, 255, 160).apply(cairo_ctx);
+        cairo_ctx.rectangle(
+            left,
+            top,
+            self.size.0 - left - right,
+            self.size.1 - top - bottom,
+        );
+        cairo_ctx.set_line_width(1.0);
+        cairo_ctx.stroke()?;
         Ok(())
     }
 
+    /// Bounding boxes of the shown items, as indices into the items of the current page.
+    fn compute_entry_rects(&self) -> Vec<(usize, Rect)> {
+        let items = &self.pages[self.cur_page].items;
+        let mut x = self.inset[3] + self.content_offset.0;
+        let mut rects = Vec::new();
+        for col in &self.columns {
+            let width = self.column_width(col);
+            let mut y = self.inset[0] + self.content_offset.1 + self.header_height();
+            for &item_i in self.column_slice(col) {
+                let height = self.row_height(&items[item_i]);
+                if items[item_i].action().is_some() {
+                    let rect = Rect {
+                        x,
+                        y,
+                        width,
+                        height,
+                    };
+                    rects.push((item_i, rect));
+                }
+                y += height + self.row_spacing;
+            }
+            x += width + self.column_padding;
+        }
+        rects
+    }
+
+    /// The item at the given surface-local position.
+    pub fn item_at(&self, x: f64, y: f64) -> Option<usize> {
+        self.entry_rects()
+            .iter()
+            .find_map(|(item_i, rect)| rect.contains(x, y).then_some(*item_i))
+    }
+
+    /// Bounding boxes of the selectable items shown, with their indices, relative to the top-left
+    /// corner of the menu. Together with `width` and `height`, this is the whole layout of the
+    /// current page, computed without a surface.
+    pub fn entry_rects(&self) -> &[(usize, Rect)] {
+        &self.entry_rects
+    }
+
+    pub fn item_action(&self, item: usize) -> Option<Action> {
+        self.pages[self.cur_page].items[item].action().cloned()
+    }
+
+    /// Show and match the keys as translated by `translate`, e.g. to the active keyboard layout.
+    /// Keys for which `translate` returns `None` are shown as written in the config.
+    pub fn set_key_translation(&mut self, translate: impl Fn(Keysym) -> Option<Keysym>) {
+        let modifier_style = self.modifier_style;
+        for page in &mut self.pages {
+            for item in &mut page.items {
+                if let ItemKind::Entry {
+                    key, layout_key, ..
+                } = &mut item.kind
+                {
+                    *layout_key = key.translated(&translate);
+                    item.key_comp = item.key_comp.with_text(layout_key.to_string());
+                    style_modifiers(modifier_style, &mut item.key_comp, layout_key);
+                }
+            }
+        }
+        self.update_layout();
+    }
+
+    /// Set the item to highlight. Returns whether the highlight changed.
+    pub fn set_hovered(&mut self, item: Option<usize>) -> bool {
+        let changed = self.hovered != item;
+        self.hovered = item;
+        changed
+    }
+
+    fn column_width(&self, col: &MenuColumn) -> f64 {
+        if self.grid_columns.is_some() {
+            return self.tile_size.0;
+        }
+        col.icon_col_width + col.key_col_width + self.key_desc_width() + col.val_col_width
+    }
+
+    /// Space between the keys and the descriptions, taken by the gap and the separator.
+    fn key_desc_width(&self) -> f64 {
+        self.key_desc_gap + self.separator.width
+    }
+
     fn render_column(
         &self,
         config: &Config,
@@ -178,44 +1237,84 @@ impl Menu {
         page: &MenuPage,
         column: &MenuColumn,
     ) -> Result<()> {
-        for (i, comp) in column.items.iter().enumerate() {
+        let items = self.column_slice(column);
+        let width = self.column_width(column);
+        let mirrored = config.auto_rtl && page.is_rtl();
+        let place = |x: f64, w: f64| place_in_row(mirrored, (dx, width), x, w);
+        let mut y = dy;
+        for (i, &item_i) in items.iter().enumerate() {
+            let comp = &page.items[item_i];
+            let row_height = self.row_height(comp);
+            if self.grid_columns.is_some() {
+                self.render_tile(config, cairo_ctx, dx, y, page, item_i, i)?;
+                y += row_height + self.row_spacing;
+                continue;
+            }
+            if comp.is_separator() {
+                self.render_separator(config, cairo_ctx, dx, y, column, comp)?;
+                y += row_height + self.row_spacing;
+                continue;
+            }
+            let fg_color = comp.color.unwrap_or(config.color);
+            let background = match self.hovered == Some(item_i) {
+                true => Some(config.highlight),
+                false => config.row_background(i),
+            };
+            if let Some(background) = background {
+                background.apply(cairo_ctx);
+                cairo_ctx.rectangle(dx, y, width, page.item_height);
+                cairo_ctx.fill()?;
+            }
+            if let Some(icon) = &comp.icon {
+                self.render_icon(
+                    cairo_ctx,
+                    icon,
+                    place(0.0, self.icon_size),
+                    y + (page.item_height - self.icon_size) * 0.5,
+                )?;
+            }
+            let key_end = column.icon_col_width + column.key_col_width;
             comp.key_comp.render(
                 cairo_ctx,
                 text::RenderOptions {
-                    x: dx + column.key_col_width - comp.key_comp.width,
-                    y: dy + page.item_height * (i as f64),
-                    fg_color: config.color,
+                    x: place(key_end - comp.key_comp.width, comp.key_comp.width),
+                    y,
+                    fg_color: config.key_color.unwrap_or(fg_color),
                     height: page.item_height,
                 },
             )?;
             self.separator.render(
                 cairo_ctx,
                 text::RenderOptions {
-                    x: dx + column.key_col_width,
-                    y: dy + page.item_height * (i as f64),
-                    fg_color: config.color,
-                    height: page.item_height,
-                },
-            )?;
-            comp.val_comp.render(
-                cairo_ctx,
-                text::RenderOptions {
-                    x: dx + column.key_col_width + self.separator.width,
-                    y: dy + page.item_height * (i as f64),
-                    fg_color: config.color,
+                    x: place(key_end + self.key_desc_gap, self.separator.width),
+                    y,
+                    fg_color: config.separator_color.unwrap_or(fg_color),
                     height: page.item_height,
                 },
             )?;
+            let val_start = key_end + self.key_desc_width();
+            let val_offset =
+                align_offset(config.text_align, width - val_start - comp.val_comp.width);
+            let val_options = text::RenderOptions {
+                x: place(val_start + val_offset, comp.val_comp.width),
+                y,
+                fg_color,
+                height: page.item_height,
+            };
+            comp.val_comp.render(cairo_ctx, val_options.clone())?;
+            if let Some(search) = self.search.as_ref().filter(|_| self.search_fuzzy)
+                && let Some((_, indices)) = fuzzy_match(&search.query, &comp.val_comp.layout.text())
+            {
+                comp.val_comp.underline(cairo_ctx, val_options, &indices)?;
+            }
+            y += row_height + self.row_spacing;
         }
 
-        if *DEBUG_LAYOUT {
+        if debug_layout() {
             Color::from_rgba(0, 0, 255, 255).apply(cairo_ctx);
-            cairo_ctx.rectangle(
-                dx,
-                dy,
-                column.key_col_width + column.val_col_width + self.separator.width,
-                column.items.len() as f64 * page.item_height,
-            );
+            // The spacing after the last row is not part of the column.
+            let height = (y - dy - self.row_spacing).max(0.0);
+            cairo_ctx.rectangle(dx, dy, self.column_width(column), height);
             cairo_ctx.set_line_width(1.0);
             cairo_ctx.stroke().unwrap();
         }
@@ -223,24 +1322,180 @@ impl Menu {
         Ok(())
     }
 
-    pub fn get_action(&self, modifiers: ModifierState, sym: Keysym) -> Option<Action> {
-        let page = &self.pages[self.cur_page];
-
-        let action = page.columns.iter().find_map(|col| {
-            col.items
-                .iter()
-                .find_map(|i| i.key.matches(sym, modifiers).then(|| i.action.clone()))
-        });
-        if action.is_some() {
-            return action;
-        }
-
-        match sym {
-            Keysym::Escape => {
-                return Some(Action::Quit);
+    /// Draw the tile of the item at `item_i`, in row `row` of the grid.
+    #[allow(clippy::too_many_arguments)]
+    fn render_tile(
+        &self,
+        config: &Config,
+        cairo_ctx: &cairo::Context,
+        x: f64,
+        y: f64,
+        page: &MenuPage,
+        item_i: usize,
+        row: usize,
+    ) -> Result<()> {
+        let comp = &page.items[item_i];
+        let (width, height) = self.tile_size;
+        let fg_color = comp.color.unwrap_or(config.color);
+        let background = match self.hovered == Some(item_i) {
+            true => Some(config.highlight),
+            false => config.row_background(row),
+        };
+        if let Some(background) = background {
+            background.apply(cairo_ctx);
+            cairo_ctx.rectangle(x, y, width, height);
+            cairo_ctx.fill()?;
+        }
+        comp.key_comp.render(
+            cairo_ctx,
+            text::RenderOptions {
+                x: x + TILE_PADDING,
+                y: y + TILE_PADDING,
+                fg_color: config.key_color.unwrap_or(fg_color),
+                height: page.item_height,
+            },
+        )?;
+        if let Some(icon) = &comp.icon {
+            self.render_icon(
+                cairo_ctx,
+                icon,
+                x + (width - self.icon_size) * 0.5,
+                y + TILE_PADDING + page.item_height,
+            )?;
+        }
+        // Descriptions line up at the bottom whether or not their tile has an icon.
+        comp.val_comp.render(
+            cairo_ctx,
+            text::RenderOptions {
+                x: x + (width - comp.val_comp.width) * 0.5,
+                y: y + height - TILE_PADDING - page.item_height,
+                fg_color,
+                height: page.item_height,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Draw a horizontal line across the column at the middle of the row, interrupted by the
+    /// centered label if there is one.
+    fn render_separator(
+        &self,
+        config: &Config,
+        cairo_ctx: &cairo::Context,
+        x: f64,
+        y: f64,
+        column: &MenuColumn,
+        item: &MenuItem,
+    ) -> Result<()> {
+        let width = self.column_width(column);
+        let height = self.row_height(item);
+        let line_y = (y + height * 0.5).floor() + 0.5;
+        config.border.apply(cairo_ctx);
+        cairo_ctx.set_line_width(1.0);
+        if item.desc.is_empty() {
+            cairo_ctx.move_to(x, line_y);
+            cairo_ctx.line_to(x + width, line_y);
+        } else {
+            let label_x = x + (width - item.val_comp.width) * 0.5;
+            let gap_start = label_x - SEPARATOR_LABEL_SPACING;
+            let gap_end = label_x + item.val_comp.width + SEPARATOR_LABEL_SPACING;
+            if gap_start > x {
+                cairo_ctx.move_to(x, line_y);
+                cairo_ctx.line_to(gap_start, line_y);
             }
-            Keysym::bracketleft | Keysym::g if modifiers.mod_ctrl => {
-                return Some(Action::Quit);
+            if gap_end < x + width {
+                cairo_ctx.move_to(gap_end, line_y);
+                cairo_ctx.line_to(x + width, line_y);
+            }
+        }
+        cairo_ctx.stroke()?;
+        item.val_comp.render(
+            cairo_ctx,
+            text::RenderOptions {
+                x: x + (width - item.val_comp.width) * 0.5,
+                y,
+                fg_color: item.color.unwrap_or(config.color),
+                height,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Paint `icon` scaled to `icon_size` with its top-left corner at (`x`, `y`).
+    fn render_icon(
+        &self,
+        cairo_ctx: &cairo::Context,
+        icon: &cairo::ImageSurface,
+        x: f64,
+        y: f64,
+    ) -> Result<()> {
+        cairo_ctx.save()?;
+        cairo_ctx.translate(x, y);
+        cairo_ctx.scale(
+            self.icon_size / icon.width() as f64,
+            self.icon_size / icon.height() as f64,
+        );
+        cairo_ctx.set_source_surface(icon, 0.0, 0.0)?;
+        cairo_ctx.paint()?;
+        cairo_ctx.restore()?;
+        Ok(())
+    }
+
+    /// The action to run again while `sym` is held: only commands of entries with `repeat`, and
+    /// nothing while searching.
+    pub fn get_repeat_action(&self, modifiers: ModifierState, sym: Keysym) -> Option<Action> {
+        if self.is_searching() {
+            return None;
+        }
+        self.get_action(modifiers, sym)
+            .filter(|action| matches!(action, Action::Exec { repeat: true, .. }))
+    }
+
+    pub fn get_action(&self, modifiers: ModifierState, sym: Keysym) -> Option<Action> {
+        let page = &self.pages[self.cur_page];
+
+        let find_entry = |matches: &dyn Fn(&Key) -> bool| {
+            page.items.iter().find_map(|i| match &i.kind {
+                ItemKind::Entry {
+                    layout_key, action, ..
+                } => matches(layout_key).then(|| action.clone()),
+                ItemKind::Separator | ItemKind::Message => None,
+            })
+        };
+        let action = find_entry(&|key| key.matches_specific(sym, modifiers));
+        if action.is_some() {
+            return action;
+        }
+
+        if self
+            .search_key
+            .as_ref()
+            .is_some_and(|k| k.matches(sym, modifiers))
+        {
+            return Some(Action::Search);
+        }
+
+        if self.quit_keys.iter().any(|k| k.matches(sym, modifiers)) {
+            return Some(Action::Quit);
+        }
+
+        if let Some(parent) = page.parent {
+            let matches =
+                |key: &Option<Key>| key.as_ref().is_some_and(|k| k.matches(sym, modifiers));
+            if matches(&self.sibling_next_key) {
+                return self.sibling_page(parent, true).map(Action::Submenu);
+            }
+            if matches(&self.sibling_prev_key) {
+                return self.sibling_page(parent, false).map(Action::Submenu);
+            }
+        }
+
+        match sym {
+            Keysym::Page_Down if self.rows_per_view.is_some() => {
+                return Some(Action::Scroll(1));
+            }
+            Keysym::Page_Up if self.rows_per_view.is_some() => {
+                return Some(Action::Scroll(-1));
             }
             Keysym::BackSpace => {
                 if let Some(parent) = page.parent {
@@ -250,11 +1505,127 @@ impl Menu {
             _ => (),
         }
 
-        None
+        // Entries with the `*` key catch everything else.
+        find_entry(&|key| key.matches(sym, modifiers))
+    }
+
+    /// Find the submenu next to the current one among the submenus of `parent`.
+    fn sibling_page(&self, parent: usize, forward: bool) -> Option<usize> {
+        let siblings: Vec<usize> = self.pages[parent]
+            .items
+            .iter()
+            .filter_map(|item| match item.action() {
+                Some(&Action::Submenu(page))
+                | Some(&Action::Exec {
+                    submenu: Some(page),
+                    ..
+                }) => Some(page),
+                _ => None,
+            })
+            .collect();
+        let cur = siblings.iter().position(|&page| page == self.cur_page)?;
+        let next = if forward {
+            match cur + 1 {
+                next if next < siblings.len() => next,
+                _ if self.sibling_wrap => 0,
+                _ => return None,
+            }
+        } else {
+            match cur.checked_sub(1) {
+                Some(prev) => prev,
+                None if self.sibling_wrap => siblings.len() - 1,
+                None => return None,
+            }
+        };
+        Some(siblings[next])
+    }
+
+    /// The page currently shown.
+    pub fn page(&self) -> usize {
+        self.cur_page
+    }
+
+    /// Whether the current page has any entries, not counting separators.
+    pub fn has_entries(&self) -> bool {
+        self.pages[self.cur_page]
+            .items
+            .iter()
+            .any(|item| !item.is_separator())
+    }
+
+    /// The page `page` is a submenu of, if any.
+    pub fn parent_page(&self, page: usize) -> Option<usize> {
+        self.pages[page].parent
     }
 
     pub fn set_page(&mut self, page: usize) {
         self.cur_page = page;
+        self.search = None;
+        self.refresh_dynamic();
+        self.update_layout();
+    }
+
+    /// The anchor of the current page or the closest of its parents which has one, `None` to use
+    /// the global anchor.
+    pub fn anchor(&self) -> Option<config::ConfigAnchor> {
+        let mut page = &self.pages[self.cur_page];
+        loop {
+            if page.anchor.is_some() {
+                return page.anchor;
+            }
+            page = &self.pages[page.parent?];
+        }
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Enter search mode with an empty query.
+    pub fn start_search(&mut self) {
+        self.set_query(String::new());
+    }
+
+    /// Handle a key press while in search mode.
+    ///
+    /// Typed text is appended to the query, BackSpace removes the last character, Escape leaves
+    /// search mode and Return selects the first matching entry.
+    pub fn search_input(&mut self, sym: Keysym, text: Option<&str>) -> Option<Action> {
+        let query = &self.search.as_ref()?.query;
+        match sym {
+            Keysym::Escape => {
+                self.search = None;
+                self.update_layout();
+            }
+            Keysym::Return | Keysym::KP_Enter => {
+                let action = self
+                    .visible_items()
+                    .find_map(|(_, item)| item.action().cloned());
+                self.search = None;
+                self.update_layout();
+                return action;
+            }
+            Keysym::BackSpace => {
+                let mut query = query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            _ => {
+                let text = text.filter(|t| !t.chars().any(char::is_control))?;
+                let query = format!("{query}{text}");
+                self.set_query(query);
+            }
+        }
+        None
+    }
+
+    fn set_query(&mut self, query: String) {
+        let prompt = format!("/{query}");
+        self.search = Some(Search {
+            prompt: ComputedText::new(prompt, &self.context, &self.font),
+            query,
+        });
+        self.update_layout();
     }
 
     pub fn navigate_to_key_sequence(&mut self, key_sequence: &str) -> Result<Option<Action>> {
@@ -263,7 +1634,9 @@ impl Menu {
             if let Some((last_key_str, _action)) = &last_action {
                 bail!("Key '{last_key_str}' leads to a command, but more keys follow in sequence");
             }
-            let key = SingleKey::from_str(key_str).map_err(Error::msg)?;
+            let key = SingleKey::from_str(key_str)
+                .map_err(Error::msg)
+                .with_context(|| format!("Invalid key '{key_str}' in initial key sequence"))?;
             match self.get_action(key.modifiers, key.keysym) {
                 Some(Action::Submenu(submenu_page)) => self.set_page(submenu_page),
                 Some(action) => last_action = Some((key_str, action)),
@@ -273,3 +1646,1265 @@ impl Menu {
         Ok(last_action.map(|x| x.1))
     }
 }
+
+/// Space between an icon and the key next to it.
+const ICON_SPACING: f64 = 4.0;
+/// Space between the divider line of a separator and its label.
+const SEPARATOR_LABEL_SPACING: f64 = 6.0;
+/// Space between the edges of a tile and its contents, with `layout: grid`.
+const TILE_PADDING: f64 = 6.0;
+/// Opacity of the footer hint relative to the text color.
+const HINT_ALPHA: f64 = 0.6;
+/// Value of `footer` replaced by the keys that work in every menu.
+const AUTO_FOOTER: &str = "auto";
+
+/// Set apart the modifiers of `key` in `comp`, which shows it, according to `style`.
+fn style_modifiers(style: config::ModifierStyle, comp: &mut ComputedText, key: &Key) {
+    let ranges = key.modifier_ranges();
+    if style == config::ModifierStyle::Plain || ranges.is_empty() {
+        return;
+    }
+    let attrs = pango::AttrList::new();
+    for range in ranges {
+        let mut attr: pango::Attribute = match style {
+            config::ModifierStyle::Plain => unreachable!(),
+            config::ModifierStyle::Dim => {
+                pango::AttrInt::new_foreground_alpha((HINT_ALPHA * 65535.0) as u16).into()
+            }
+            config::ModifierStyle::Bold => pango::AttrInt::new_weight(pango::Weight::Bold).into(),
+            config::ModifierStyle::Small => pango::AttrFloat::new_scale(pango::SCALE_SMALL).into(),
+        };
+        attr.set_start_index(range.start as u32);
+        attr.set_end_index(range.end as u32);
+        attrs.insert(attr);
+    }
+    comp.set_attributes(&attrs);
+}
+
+/// The footer hint listing the keys that work in every menu.
+fn auto_footer(config: &Config) -> String {
+    let mut hints = Vec::new();
+    if let Some(key) = config.quit_keys.first() {
+        hints.push(format!("{}: close", key.repr));
+    }
+    hints.push("BackSpace: back".to_owned());
+    if let Some(key) = &config.search_key {
+        hints.push(format!("{key}: search"));
+    }
+    if let Some(key) = &config.sibling_next_key {
+        hints.push(format!("{key}: next submenu"));
+    }
+    if let Some(key) = &config.sibling_prev_key {
+        hints.push(format!("{key}: previous submenu"));
+    }
+    hints.join("   ")
+}
+
+/// Position of something `w` wide at offset `x` from the start of the row `start..start + width`,
+/// which is its right edge if `mirrored`.
+fn place_in_row(mirrored: bool, (start, width): (f64, f64), x: f64, w: f64) -> f64 {
+    match mirrored {
+        true => start + width - x - w,
+        false => start + x,
+    }
+}
+
+/// Offset of a description aligned by `align` in a space `free` wider than it.
+fn align_offset(align: config::TextAlign, free: f64) -> f64 {
+    let offset = match align {
+        config::TextAlign::Left => 0.0,
+        config::TextAlign::Center => free * 0.5,
+        config::TextAlign::Right => free,
+    };
+    offset.max(0.0)
+}
+
+/// Match the characters of `query` in order in `text`, ignoring case. Returns a score, which is
+/// higher for consecutive characters and characters starting a word, and the byte indices of the
+/// matched characters.
+fn fuzzy_match(query: &str, text: &str) -> Option<(usize, Vec<usize>)> {
+    let mut query = query.chars().peekable();
+    let mut score = 0;
+    let mut indices = Vec::new();
+    let mut prev: Option<(usize, char)> = None;
+    for (i, c) in text.char_indices() {
+        let Some(&q) = query.peek() else {
+            break;
+        };
+        if c.to_lowercase().eq(q.to_lowercase()) {
+            query.next();
+            score += 1;
+            if prev.is_some_and(|(prev_i, _)| indices.last() == Some(&prev_i)) {
+                score += 2;
+            }
+            if prev.is_none_or(|(_, prev_c)| !prev_c.is_alphanumeric()) {
+                score += 3;
+            }
+            indices.push(i);
+        }
+        prev = Some((i, c));
+    }
+    query.peek().is_none().then_some((score, indices))
+}
+
+/// Load a PNG icon. Failures are reported but not fatal.
+fn load_icon(path: &Path) -> Option<cairo::ImageSurface> {
+    let path = config::expand_home(path);
+    let icon = File::open(&path)
+        .map_err(Error::from)
+        .and_then(|mut file| Ok(cairo::ImageSurface::create_from_png(&mut file)?));
+    match icon {
+        Ok(icon) => Some(icon),
+        Err(err) => {
+            eprintln!("Warning: cannot load icon {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay_client_toolkit::reexports::calloop::channel;
+
+    use super::*;
+
+    fn menu(config: &str) -> Menu {
+        Menu::new(&serde_yaml::from_str(config).unwrap()).unwrap()
+    }
+
+    const SIBLINGS: &str = "sibling_next_key: n
+sibling_prev_key: p
+menu:
+  - { key: a, desc: A, submenu: [{ key: x, desc: X, cmd: x }] }
+  - { key: c, desc: Cmd, cmd: c }
+  - { key: b, desc: B, submenu: [{ key: x, desc: X, cmd: x }] }
+  - { key: d, desc: D, submenu: [{ key: x, desc: X, cmd: x }] }";
+
+    /// The page opened by `sym` on `page`.
+    fn sibling(menu: &mut Menu, page: usize, sym: Keysym) -> Option<usize> {
+        menu.set_page(page);
+        match menu.get_action(ModifierState::default(), sym) {
+            Some(Action::Submenu(page)) => Some(page),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn sibling_submenus() {
+        let mut menu = menu(SIBLINGS);
+        assert_eq!(sibling(&mut menu, 1, Keysym::n), Some(2));
+        assert_eq!(sibling(&mut menu, 2, Keysym::n), Some(3));
+        assert_eq!(sibling(&mut menu, 3, Keysym::p), Some(2));
+        assert_eq!(sibling(&mut menu, 3, Keysym::n), None);
+        assert_eq!(sibling(&mut menu, 1, Keysym::p), None);
+        // Only submenus have siblings.
+        assert_eq!(sibling(&mut menu, 0, Keysym::n), None);
+    }
+
+    #[test]
+    fn sibling_submenus_wrap() {
+        let mut menu = menu(&format!("sibling_wrap: true\n{SIBLINGS}"));
+        assert_eq!(sibling(&mut menu, 3, Keysym::n), Some(1));
+        assert_eq!(sibling(&mut menu, 1, Keysym::p), Some(3));
+        assert_eq!(sibling(&mut menu, 1, Keysym::n), Some(2));
+    }
+
+    #[test]
+    fn submenu_cmd() {
+        let mut menu = menu(
+            "menu:
+  - { key: a, desc: A, submenu: [{ key: x, desc: X, cmd: x }] }
+  - { key: m, desc: Music, cmd: [mpd, ncmpcpp], submenu: [{ key: p, desc: Pause, cmd: mpc pause }] }
+  - { key: b, desc: B, submenu: [{ key: x, desc: X, cmd: x }] }
+sibling_next_key: n
+sibling_prev_key: p",
+        );
+        let Some(Action::Exec {
+            cmd,
+            keep_open: true,
+            submenu: Some(music),
+            ..
+        }) = menu.get_action(ModifierState::default(), Keysym::m)
+        else {
+            panic!("expected a command opening a submenu");
+        };
+        assert_eq!(cmd, ["mpd", "ncmpcpp"]);
+        menu.set_page(music);
+        assert_eq!(visible(&menu), ["Pause"]);
+
+        // The submenu is a sibling of the others, although opening it runs a command.
+        assert_eq!(sibling(&mut menu, 1, Keysym::n), Some(music));
+        assert_eq!(sibling(&mut menu, music, Keysym::n), Some(music + 1));
+        assert_eq!(sibling(&mut menu, music + 1, Keysym::n), None);
+        menu.sibling_wrap = true;
+        assert_eq!(sibling(&mut menu, music + 1, Keysym::n), Some(1));
+        assert_eq!(sibling(&mut menu, 1, Keysym::p), Some(music + 1));
+    }
+
+    #[test]
+    fn entry_color() {
+        let menu = menu(
+            "menu:
+  - { key: a, desc: A, cmd: a, color: '#fb4934' }
+  - { key: b, desc: B, cmd: b }
+  - { key: s, desc: S, color: '#00ff00', submenu: [{ key: x, desc: X, cmd: x }] }",
+        );
+        // Opaque colors have a NaN alpha, so compare their debug representations.
+        let colors: Vec<_> = menu.pages[0]
+            .items
+            .iter()
+            .map(|item| item.color.map(|color| format!("{color:?}")))
+            .collect();
+        let color = |hex: &str| Some(format!("{:?}", hex.parse::<Color>().unwrap()));
+        assert_eq!(colors, [color("#fb4934"), None, color("#00ff00")]);
+    }
+
+    /// Descriptions of the entries laid out on the current page.
+    fn visible(menu: &Menu) -> Vec<&str> {
+        let page = &menu.pages[menu.cur_page];
+        menu.columns
+            .iter()
+            .flat_map(|col| &col.items)
+            .map(|&i| page.items[i].desc.as_str())
+            .collect()
+    }
+
+    const SEARCH: &str = "search_key: s
+menu:
+  - { key: f, desc: Firefox, cmd: firefox }
+  - { key: t, desc: Terminal, cmd: foot }
+  - { key: h, desc: Thunderbird, cmd: thunderbird }";
+
+    fn type_query(menu: &mut Menu, query: &str) {
+        for c in query.chars() {
+            let sym = Keysym::from_char(c);
+            assert!(menu.search_input(sym, Some(&c.to_string())).is_none());
+        }
+    }
+
+    #[test]
+    fn search_filters_by_description() {
+        let mut menu = menu(SEARCH);
+        assert!(matches!(
+            menu.get_action(ModifierState::default(), Keysym::s),
+            Some(Action::Search)
+        ));
+        menu.start_search();
+        assert!(menu.is_searching());
+        assert_eq!(visible(&menu), ["Firefox", "Terminal", "Thunderbird"]);
+
+        type_query(&mut menu, "Ir");
+        assert_eq!(visible(&menu), ["Firefox", "Thunderbird"]);
+        type_query(&mut menu, "e");
+        assert_eq!(visible(&menu), ["Firefox"]);
+
+        assert!(menu.search_input(Keysym::BackSpace, None).is_none());
+        assert_eq!(visible(&menu), ["Firefox", "Thunderbird"]);
+
+        type_query(&mut menu, "x");
+        assert!(visible(&menu).is_empty());
+    }
+
+    #[test]
+    fn search_escape_clears_filter() {
+        let mut menu = menu(SEARCH);
+        menu.start_search();
+        type_query(&mut menu, "term");
+        assert!(menu.search_input(Keysym::Escape, None).is_none());
+        assert!(!menu.is_searching());
+        assert_eq!(visible(&menu), ["Firefox", "Terminal", "Thunderbird"]);
+    }
+
+    #[test]
+    fn search_return_selects_first_match() {
+        let mut menu = menu(SEARCH);
+        menu.start_search();
+        type_query(&mut menu, "th");
+        let action = menu.search_input(Keysym::Return, None);
+        assert!(matches!(action, Some(Action::Exec { cmd, .. }) if cmd == ["thunderbird"]));
+        assert!(!menu.is_searching());
+    }
+
+    /// Write a `size`×`size` PNG under the temporary directory and return its path.
+    fn write_icon(name: &str, size: i32) -> String {
+        let dir = std::env::temp_dir().join(format!("wlr-which-key-icons-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, size, size).unwrap();
+        surface
+            .write_to_png(&mut File::create(&path).unwrap())
+            .unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn icons() {
+        let icon = write_icon("icon.png", 8);
+        let menu = menu(&format!(
+            "icon_size: 40
+menu:
+  - {{ key: a, desc: A, cmd: a, icon: {icon} }}
+  - {{ key: b, desc: B, cmd: b }}"
+        ));
+        let page = &menu.pages[0];
+        assert!(page.items[0].icon.is_some());
+        assert!(page.items[1].icon.is_none());
+        // Rows are tall enough for the icon, which is scaled to `icon_size`.
+        assert_eq!(page.item_height, 40.0);
+        assert_eq!(menu.columns[0].icon_col_width, 40.0 + ICON_SPACING);
+    }
+
+    #[test]
+    fn missing_icon() {
+        let plain = menu("menu: [{ key: a, desc: A, cmd: a }]");
+        let menu =
+            menu("icon_size: 40\nmenu: [{ key: a, desc: A, cmd: a, icon: /nonexistent.png }]");
+        assert!(menu.pages[0].items[0].icon.is_none());
+        assert_eq!(menu.columns[0].icon_col_width, 0.0);
+        assert_eq!(menu.pages[0].item_height, plain.pages[0].item_height);
+    }
+
+    #[test]
+    fn breadcrumb() {
+        let config = "separator: ' > '
+menu:
+  - key: a
+    desc: Apps
+    submenu:
+      - { key: b, desc: Browsers, submenu: [{ key: f, desc: Firefox, cmd: firefox }] }";
+        let mut menu = menu(&format!("show_breadcrumb: true\n{config}"));
+        assert!(menu.pages[0].breadcrumb.is_none());
+        assert_eq!(menu.header_height(), 0.0);
+        let text =
+            |menu: &Menu, page: usize| menu.pages[page].breadcrumb.as_ref().unwrap().layout.text();
+        assert_eq!(text(&menu, 1), "Apps");
+        assert_eq!(text(&menu, 2), "Apps > Browsers");
+        menu.set_page(2);
+        assert_eq!(menu.header_height(), menu.pages[2].item_height);
+
+        let menu = self::menu(config);
+        assert!(menu.pages.iter().all(|page| page.breadcrumb.is_none()));
+    }
+
+    #[test]
+    fn submenu_title() {
+        let mut menu = menu(
+            "menu:
+  - { key: a, desc: A, title: Applications, submenu: [{ key: x, desc: X, cmd: x }] }
+  - { key: b, desc: B, submenu: [{ key: x, desc: X, cmd: x }] }",
+        );
+        let title = menu.pages[1].title.as_ref().unwrap();
+        assert_eq!(title.layout.text(), "Applications");
+        assert!(menu.pages[2].title.is_none());
+
+        menu.set_page(1);
+        let title_height = menu.pages[1].title.as_ref().unwrap().height;
+        assert_eq!(
+            menu.header_height(),
+            title_height.max(menu.pages[1].item_height)
+        );
+        menu.set_page(2);
+        assert_eq!(menu.header_height(), 0.0);
+    }
+
+    #[test]
+    fn item_at() {
+        let config: Config = serde_yaml::from_str(
+            "padding: 10
+border_width: 0
+column_padding: 20
+rows_per_column: 2
+menu:
+  - { key: a, desc: A, cmd: a }
+  - { key: b, desc: B, cmd: b }
+  - { key: c, desc: C, cmd: c }",
+        )
+        .unwrap();
+        let menu = Menu::new(&config).unwrap();
+        let height = menu.pages[0].item_height;
+        let width = menu.column_width(&menu.columns[0]);
+
+        assert_eq!(menu.item_at(10.0, 10.0), Some(0));
+        assert_eq!(menu.item_at(10.0 + width - 0.5, 10.0 + height), Some(1));
+        assert_eq!(menu.item_at(30.0 + width, 10.0), Some(2));
+        // Padding, the gap between columns and the empty cell below the last item.
+        assert_eq!(menu.item_at(5.0, 10.0), None);
+        assert_eq!(menu.item_at(20.0 + width, 10.0), None);
+        assert_eq!(menu.item_at(30.0 + width, 10.0 + height), None);
+        assert!(matches!(menu.item_action(2), Some(Action::Exec { cmd, .. }) if cmd == ["c"]));
+    }
+
+    #[test]
+    fn item_at_below_header() {
+        let config: Config = serde_yaml::from_str(
+            "padding: 0
+border_width: 0
+search_key: s
+menu: [{ key: a, desc: A, cmd: a }]",
+        )
+        .unwrap();
+        let mut menu = Menu::new(&config).unwrap();
+        let height = menu.pages[0].item_height;
+        assert_eq!(menu.item_at(1.0, 1.0), Some(0));
+        menu.start_search();
+        assert_eq!(menu.item_at(1.0, 1.0), None);
+        assert_eq!(menu.item_at(1.0, height + 1.0), Some(0));
+    }
+
+    #[test]
+    fn hovered_item() {
+        let mut menu = menu("menu: [{ key: a, desc: A, submenu: [{ key: x, desc: X, cmd: x }] }]");
+        assert!(menu.set_hovered(Some(0)));
+        assert!(!menu.set_hovered(Some(0)));
+        menu.set_page(1);
+        assert_eq!(menu.hovered, None);
+    }
+
+    /// A menu with ten entries named after the letters a to j.
+    fn ten_entries() -> Menu {
+        let entries: Vec<_> = ('a'..='j')
+            .map(|c| format!("  - {{ key: {c}, desc: {c}, cmd: {c} }}"))
+            .collect();
+        menu(&format!(
+            "padding: 0\nborder_width: 0\nmenu:\n{}",
+            entries.join("\n")
+        ))
+    }
+
+    fn shown(menu: &Menu) -> Vec<usize> {
+        menu.column_slice(&menu.columns[0]).to_vec()
+    }
+
+    #[test]
+    fn paginate() {
+        let mut menu = ten_entries();
+        let item_height = menu.pages[0].item_height;
+        assert_eq!(menu.rows_per_view, None);
+
+        // Room for four and a half rows, one of which is taken by the page indicator.
+        menu.set_output_height(item_height * 4.5);
+        assert_eq!(menu.rows_per_view, Some(3));
+        assert_eq!(shown(&menu), [0, 1, 2]);
+        assert_eq!(menu.scroll_indicator.as_ref().unwrap().layout.text(), "1/4");
+
+        assert!(menu.scroll_by(1));
+        assert_eq!(shown(&menu), [3, 4, 5]);
+        assert!(menu.scroll_by(5));
+        assert_eq!(shown(&menu), [9]);
+        assert_eq!(menu.scroll_indicator.as_ref().unwrap().layout.text(), "4/4");
+        assert!(!menu.scroll_by(1));
+        assert!(menu.scroll_by(-10));
+        assert_eq!(shown(&menu), [0, 1, 2]);
+        assert!(!menu.scroll_by(-1));
+
+        // Everything fits again.
+        menu.set_output_height(item_height * 20.0);
+        assert_eq!(menu.rows_per_view, None);
+        assert_eq!(shown(&menu).len(), 10);
+        assert!(!menu.scroll_by(1));
+    }
+
+    #[test]
+    fn paginate_without_rows() {
+        let mut menu = ten_entries();
+        menu.start_search();
+        type_query(&mut menu, "zz");
+        assert_eq!(menu.rows(), 0);
+        // Not even the search prompt fits.
+        menu.set_output_height(1.0);
+        assert_eq!(menu.scroll, 0);
+        assert!(!menu.scroll_by(1));
+    }
+
+    #[test]
+    fn render_keeps_layout() {
+        let config: Config = serde_yaml::from_str(
+            "menu:\n  - { key: a, desc: A, cmd: a }\n  - { key: b, desc: B, cmd: b }",
+        )
+        .unwrap();
+        let menu = Menu::new(&config).unwrap();
+        let size = (menu.width(), menu.height());
+        let rects = menu.entry_rects.to_vec();
+        let rects_ptr = menu.entry_rects.as_ptr();
+
+        let surface = cairo::ImageSurface::create(
+            cairo::Format::ARgb32,
+            size.0.ceil() as i32,
+            size.1.ceil() as i32,
+        )
+        .unwrap();
+        let cairo_ctx = cairo::Context::new(&surface).unwrap();
+        for _ in 0..2 {
+            menu.render(&config, &cairo_ctx).unwrap();
+            assert_eq!((menu.width(), menu.height()), size);
+            assert_eq!(menu.entry_rects, rects);
+            // The same allocation, so the rectangles were not computed again.
+            assert_eq!(menu.entry_rects.as_ptr(), rects_ptr);
+        }
+    }
+
+    #[test]
+    fn copy_entry() {
+        let menu = menu("menu: [{ key: e, desc: Email, copy: me@example.com }]");
+        assert!(matches!(
+            menu.get_action(ModifierState::default(), Keysym::e),
+            Some(Action::Copy(text)) if text == "me@example.com"
+        ));
+    }
+
+    #[test]
+    fn navigate_to_keep_open_command() {
+        let mut menu = menu(
+            "menu:
+  - key: p
+    desc: Player
+    submenu: [{ key: n, desc: Next, cmd: playerctl next, keep_open: true }]",
+        );
+        let action = menu.navigate_to_key_sequence("p n").unwrap();
+        assert!(matches!(
+            action,
+            Some(Action::Exec {
+                keep_open: true,
+                ..
+            })
+        ));
+        // The menu is shown at the submenu holding the command.
+        assert_eq!(menu.cur_page, 1);
+
+        assert!(menu.navigate_to_key_sequence("n n").is_err());
+    }
+
+    #[test]
+    fn invalid_initial_key() {
+        let mut menu =
+            menu("menu: [{ key: Ctrl+a, desc: A, submenu: [{ key: s, desc: S, cmd: s }] }]");
+        let err = menu.navigate_to_key_sequence("Meta+a s").err().unwrap();
+        assert_eq!(
+            format!("{err:#}"),
+            "Invalid key 'Meta+a' in initial key sequence: unknown modifier 'Meta'"
+        );
+        assert!(menu.navigate_to_key_sequence("Ctrl+a s").unwrap().is_some());
+    }
+
+    #[test]
+    fn entry_font() {
+        let menu = menu(
+            "font: monospace 10
+menu:
+  - { key: a, desc: A, cmd: a }
+  - { key: b, desc: B, cmd: b, font: monospace 30 }",
+        );
+        let items = &menu.pages[0].items;
+        assert!(items[1].val_comp.height > items[0].val_comp.height * 2.0);
+        assert!(items[1].key_comp.height > items[0].key_comp.height * 2.0);
+    }
+
+    #[test]
+    fn separators_cannot_be_selected() {
+        let menu = menu(
+            "menu:
+  - { key: a, desc: A, cmd: a }
+  - separator: Tools
+  - { key: b, desc: B, cmd: b }",
+        );
+        assert_eq!(menu.pages[0].items.len(), 3);
+        assert!(menu.item_action(1).is_none());
+        assert!(matches!(
+            menu.get_action(ModifierState::default(), Keysym::b),
+            Some(Action::Exec { cmd, .. }) if cmd == ["b"]
+        ));
+    }
+
+    #[test]
+    fn key_translation() {
+        let mut menu = menu("menu: [{ key: Ctrl+a, desc: A, cmd: a }]");
+        menu.set_key_translation(|keysym| (keysym == Keysym::a).then_some(Keysym::Cyrillic_ef));
+        let ItemKind::Entry { layout_key, .. } = &menu.pages[0].items[0].kind else {
+            panic!("not an entry");
+        };
+        assert_eq!(layout_key.to_string(), "Ctrl+ф");
+        let ctrl = ModifierState {
+            mod_ctrl: true,
+            ..Default::default()
+        };
+        assert!(menu.get_action(ctrl, Keysym::Cyrillic_ef).is_some());
+    }
+
+    /// Descriptions of the items on the current page.
+    fn descs(menu: &Menu) -> Vec<&str> {
+        menu.pages[menu.cur_page]
+            .items
+            .iter()
+            .map(|item| item.desc.as_str())
+            .collect()
+    }
+
+    /// Wait for `runs` outputs of dynamic entries run in the background and show them.
+    fn finish_dynamic(menu: &mut Menu, channel: &channel::Channel<DynamicOutput>, runs: usize) {
+        for _ in 0..runs {
+            menu.finish_dynamic(channel.recv().unwrap());
+        }
+    }
+
+    #[test]
+    fn dynamic_entries() {
+        let mut menu = menu(
+            r"menu:
+  - { key: a, desc: A, cmd: a }
+  - dynamic: printf 'b\tB\techo b\nc\tC\n'
+  - dynamic: exit 1
+  - { key: d, desc: D, cmd: d }",
+        );
+        let (sender, channel) = channel::channel();
+        menu.set_dynamic_sender(sender);
+        finish_dynamic(&mut menu, &channel, 2);
+        assert_eq!(
+            descs(&menu),
+            ["A", "B", "C", "'exit 1' failed: exit status: 1", "D"]
+        );
+        assert!(matches!(
+            menu.get_action(ModifierState::default(), Keysym::b),
+            Some(Action::Exec { cmd, path, .. }) if cmd == ["echo b"] && path == ["b"]
+        ));
+    }
+
+    #[test]
+    fn dynamic_entries_refresh() {
+        let file =
+            std::env::temp_dir().join(format!("wlr-which-key-dynamic-{}", std::process::id()));
+        std::fs::write(&file, "a\tFirst\n").unwrap();
+        let mut menu = menu(&format!(
+            "menu:
+  - dynamic: cat {0}
+    refresh: true
+  - dynamic: cat {0}
+  - {{ key: z, desc: Z, cmd: z }}",
+            file.display()
+        ));
+        let (sender, channel) = channel::channel();
+        menu.set_dynamic_sender(sender);
+        finish_dynamic(&mut menu, &channel, 2);
+        assert_eq!(descs(&menu), ["First", "First", "Z"]);
+        std::fs::write(&file, "a\tSecond\nb\tThird\n").unwrap();
+        menu.set_page(0);
+        // Only the refreshing entry runs again.
+        assert_eq!(descs(&menu), ["Loading…", "First", "Z"]);
+        finish_dynamic(&mut menu, &channel, 1);
+        assert_eq!(descs(&menu), ["Second", "Third", "First", "Z"]);
+    }
+
+    #[test]
+    fn confirm_command() {
+        let mut menu = menu("menu: [{ key: p, desc: Power off, cmd: poweroff, confirm: Sure? }]");
+        let Some(Action::Confirm(page)) = menu.get_action(ModifierState::default(), Keysym::p)
+        else {
+            panic!("no confirmation");
+        };
+        menu.set_page(page);
+        assert_eq!(descs(&menu), ["Yes", "No"]);
+        assert!(matches!(
+            menu.get_action(ModifierState::default(), Keysym::y),
+            Some(Action::Exec { cmd, .. }) if cmd == ["poweroff"]
+        ));
+        assert!(matches!(
+            menu.get_action(ModifierState::default(), Keysym::n),
+            Some(Action::Submenu(0))
+        ));
+    }
+
+    #[test]
+    fn status_line() {
+        let mut menu = menu("menu: [{ key: a, desc: A, cmd: a }]");
+        let height = menu.height();
+        menu.set_status("done");
+        assert!(menu.height() > height);
+        menu.set_status("");
+        assert_eq!(menu.height(), height);
+    }
+
+    #[test]
+    fn repeat_only_repeating_commands() {
+        let mut menu = menu(
+            "menu:
+  - { key: u, desc: Up, cmd: up, keep_open: true, repeat: true }
+  - { key: d, desc: Down, cmd: down, keep_open: true }
+  - { key: s, desc: Sub, submenu: [{ key: x, desc: X, cmd: x }] }",
+        );
+        let repeat = |menu: &Menu, sym| menu.get_repeat_action(ModifierState::default(), sym);
+        assert!(matches!(
+            repeat(&menu, Keysym::u),
+            Some(Action::Exec { cmd, .. }) if cmd == ["up"]
+        ));
+        assert!(repeat(&menu, Keysym::d).is_none());
+        assert!(repeat(&menu, Keysym::s).is_none());
+        menu.start_search();
+        assert!(repeat(&menu, Keysym::u).is_none());
+    }
+
+    /// Render `config` and return the pixels.
+    fn rendered_pixels(config: &str) -> Vec<u8> {
+        let config: Config = serde_yaml::from_str(config).unwrap();
+        let menu = Menu::new(&config).unwrap();
+        let mut surface = cairo::ImageSurface::create(
+            cairo::Format::ARgb32,
+            menu.width().ceil() as i32,
+            menu.height().ceil() as i32,
+        )
+        .unwrap();
+        menu.render(&config, &cairo::Context::new(&surface).unwrap())
+            .unwrap();
+        surface.data().unwrap().to_vec()
+    }
+
+    /// Render `config` and return whether any pixel is mostly `red`, `green` or `blue`.
+    fn rendered_channels(config: &str) -> [bool; 3] {
+        // Pixels are stored as BGRA on little-endian machines.
+        let mut found = [false; 3];
+        for pixel in rendered_pixels(config).chunks(4) {
+            let [b, g, r, _] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            for (i, value) in [r, g, b].into_iter().enumerate() {
+                let others = [r, g, b].into_iter().map(u32::from).sum::<u32>() - u32::from(value);
+                found[i] |= value > 100 && others < 40;
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn key_color() {
+        let config = "color: '#00ff00'\nmenu: [{ key: a, desc: A, cmd: a }]";
+        assert_eq!(rendered_channels(config), [false, true, false]);
+        let config = format!("key_color: '#ff0000'\n{config}");
+        assert_eq!(rendered_channels(&config), [true, true, false]);
+    }
+
+    #[test]
+    fn separator_color() {
+        let config =
+            "color: '#00ff00'\nseparator_color: '#0000ff'\nmenu: [{ key: a, desc: A, cmd: a }]";
+        assert_eq!(rendered_channels(config), [false, true, true]);
+        // Without a separator there is nothing to draw in that color.
+        let config = format!("separator: ''\n{config}");
+        assert_eq!(rendered_channels(&config), [false, true, false]);
+    }
+
+    #[test]
+    fn auto_footer_lists_configured_keys() {
+        let config: Config = serde_yaml::from_str("menu: []").unwrap();
+        assert_eq!(auto_footer(&config), "Escape: close   BackSpace: back");
+        let config: Config =
+            serde_yaml::from_str("search_key: F1\nsibling_next_key: n\nmenu: []").unwrap();
+        assert_eq!(
+            auto_footer(&config),
+            "Escape: close   BackSpace: back   F1: search   n: next submenu"
+        );
+    }
+
+    #[test]
+    fn footer_hint() {
+        let plain = menu("menu: [{ key: a, desc: A, cmd: a }]");
+        let menu = menu("footer: hello\nmenu: [{ key: a, desc: A, cmd: a }]");
+        assert!(menu.height() > plain.height());
+        assert_eq!(menu.footer().count(), 1);
+    }
+
+    #[test]
+    fn rtl_pages() {
+        let menu = menu(
+            "menu:
+  - { key: a, desc: '123', cmd: a }
+  - { key: b, desc: שלום, cmd: b }
+  - { key: c, desc: Hello, submenu: [{ key: x, desc: X, cmd: x }] }",
+        );
+        // Digits have no strong direction, so the second entry decides.
+        assert!(menu.pages[0].is_rtl());
+        assert!(!menu.pages[1].is_rtl());
+    }
+
+    #[test]
+    fn place_mirrored() {
+        assert_eq!(place_in_row(false, (10.0, 100.0), 5.0, 20.0), 15.0);
+        assert_eq!(place_in_row(true, (10.0, 100.0), 5.0, 20.0), 85.0);
+        assert_eq!(place_in_row(true, (10.0, 100.0), 0.0, 100.0), 10.0);
+    }
+
+    #[test]
+    fn fit_max_width() {
+        let config = "menu:
+  - { key: a, desc: A rather long description that does not fit, cmd: a }
+  - { key: b, desc: B, cmd: b }";
+        let wide = menu(config);
+        let narrow = menu(&format!("max_width: 150\n{config}"));
+        assert!(wide.width() > 150.0);
+        assert!(narrow.width() <= 150.0);
+        let items = &narrow.pages[0].items;
+        assert!(items[0].val_comp.layout.is_ellipsized());
+        assert!(!items[1].val_comp.layout.is_ellipsized());
+        // Menus which fit are left alone.
+        let fits = menu(&format!("max_width: 1000\n{config}"));
+        assert_eq!(fits.width(), wide.width());
+    }
+
+    #[test]
+    fn modifier_sides() {
+        let menu = menu(
+            "menu:
+  - { key: LCtrl+a, desc: Left, cmd: left }
+  - { key: Ctrl+a, desc: Any, cmd: any }",
+        );
+        let mut modifiers = ModifierState {
+            mod_ctrl: true,
+            ..Default::default()
+        };
+        modifiers.sides.update(Keysym::Control_R, true);
+        assert!(matches!(
+            menu.get_action(modifiers, Keysym::a),
+            Some(Action::Exec { cmd, .. }) if cmd == ["any"]
+        ));
+        modifiers.sides.update(Keysym::Control_L, true);
+        assert!(matches!(
+            menu.get_action(modifiers, Keysym::a),
+            Some(Action::Exec { cmd, .. }) if cmd == ["left"]
+        ));
+    }
+
+    #[test]
+    fn auto_columns_are_balanced() {
+        let entries: String = "abcdefg"
+            .chars()
+            .map(|key| format!("  - {{ key: {key}, desc: {key}, cmd: {key} }}\n"))
+            .collect();
+        let column_sizes = |mode: &str| -> Vec<usize> {
+            let menu = menu(&format!(
+                "rows_per_column: 5\ncolumns: {mode}\nmenu:\n{entries}"
+            ));
+            menu.columns.iter().map(|col| col.items.len()).collect()
+        };
+        assert_eq!(column_sizes("fill"), [5, 2]);
+        assert_eq!(column_sizes("auto"), [4, 3]);
+    }
+
+    #[test]
+    fn columns_sized_to_own_items() {
+        let menu = menu(
+            "rows_per_column: 1
+menu:
+  - { key: a, desc: A very long description, cmd: a }
+  - { key: b, desc: B, cmd: b }",
+        );
+        let [long, short] = &menu.columns[..] else {
+            panic!("expected two columns");
+        };
+        assert!(short.val_col_width < long.val_col_width);
+    }
+
+    #[test]
+    fn fuzzy_match_in_order() {
+        let (score, indices) = fuzzy_match("frfx", "Firefox").unwrap();
+        assert_eq!(indices, [0, 2, 4, 6]);
+        assert!(score > 0);
+        assert!(fuzzy_match("fxf", "Firefox").is_none());
+        assert!(fuzzy_match("frfx", "Terminal").is_none());
+    }
+
+    #[test]
+    fn fuzzy_search_order() {
+        let mut menu = menu(
+            "search_fuzzy: true\nsearch_key: s\nmenu:\n  - { key: p, desc: Profile, cmd: p }\n  - { key: t, desc: Terminal, cmd: t }\n  - { key: l, desc: Files, cmd: l }\n  - { key: f, desc: Firefox, cmd: f }",
+        );
+        menu.start_search();
+        type_query(&mut menu, "fi");
+        // Files and Firefox match equally well, and stay in config order.
+        assert_eq!(visible(&menu), ["Files", "Firefox", "Profile"]);
+    }
+
+    #[test]
+    fn keep_open_default() {
+        let menu = menu(
+            "keep_open_default: true
+menu:
+  - { key: a, desc: A, cmd: a }
+  - { key: b, desc: B, cmd: b, keep_open: false }",
+        );
+        let none = ModifierState::default();
+        assert!(matches!(
+            menu.get_action(none, Keysym::a),
+            Some(Action::Exec {
+                keep_open: true,
+                ..
+            })
+        ));
+        assert!(matches!(
+            menu.get_action(none, Keysym::b),
+            Some(Action::Exec {
+                keep_open: false,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn grid_fills_rows() {
+        let menu = menu(
+            "layout: grid
+grid_columns: 2
+menu:
+  - { key: a, desc: A, cmd: a }
+  - separator: Apps
+  - { key: b, desc: B, cmd: b }
+  - { key: c, desc: C, cmd: c }",
+        );
+        let columns: Vec<_> = menu.columns.iter().map(|col| col.items.clone()).collect();
+        // Tiles fill rows, so each column has every other tile.
+        assert_eq!(columns, [vec![0, 3], vec![2]]);
+        let (width, height) = menu.tile_size;
+        assert_eq!(menu.column_width(&menu.columns[1]), width);
+        assert!(height > menu.pages[0].item_height * 2.0);
+    }
+
+    #[test]
+    fn submenus_inherit_anchor() {
+        let mut menu = menu(
+            "menu:
+  - key: p
+    desc: Power
+    anchor: bottom
+    submenu:
+      - key: s
+        desc: Session
+        submenu: [{ key: l, desc: Lock, cmd: lock }]
+  - { key: f, desc: Files, cmd: files }",
+        );
+        assert!(menu.anchor().is_none());
+        let none = ModifierState::default();
+        for sym in [Keysym::p, Keysym::s] {
+            let Some(Action::Submenu(page)) = menu.get_action(none, sym) else {
+                panic!("not a submenu");
+            };
+            menu.set_page(page);
+            assert!(matches!(menu.anchor(), Some(config::ConfigAnchor::Bottom)));
+        }
+    }
+
+    fn ctrl() -> ModifierState {
+        ModifierState {
+            mod_ctrl: true,
+            ..Default::default()
+        }
+    }
+
+    fn is_quit(action: Option<Action>) -> bool {
+        matches!(action, Some(Action::Quit))
+    }
+
+    #[test]
+    fn dynamic_entries_load_in_background() {
+        let mut menu = menu(
+            r"menu:
+  - dynamic: printf 'x\tX\techo x\n'
+    refresh: true
+  - { key: a, desc: A, cmd: a }",
+        );
+        assert_eq!(descs(&menu), ["Loading…", "A"]);
+        assert_eq!(menu.entry_rects().len(), 1);
+
+        let (sender, channel) = channel::channel();
+        menu.set_dynamic_sender(sender);
+        assert_eq!(descs(&menu), ["Loading…", "A"]);
+        let first = channel.recv().unwrap();
+
+        // Opening the page again runs the command again, so the first output is outdated.
+        menu.set_page(0);
+        assert!(!menu.finish_dynamic(first));
+        assert_eq!(descs(&menu), ["Loading…", "A"]);
+
+        assert!(menu.finish_dynamic(channel.recv().unwrap()));
+        assert_eq!(descs(&menu), ["X", "A"]);
+        assert_eq!(menu.entry_rects().len(), 2);
+        let action = menu.get_action(ModifierState::default(), Keysym::x);
+        assert!(matches!(action, Some(Action::Exec { desc, .. }) if desc == "X"));
+    }
+
+    #[test]
+    fn default_quit_keys() {
+        let menu = menu("menu: [{ key: a, desc: A, cmd: a }]");
+        let none = ModifierState::default();
+        assert!(is_quit(menu.get_action(none, Keysym::Escape)));
+        assert!(is_quit(menu.get_action(ctrl(), Keysym::bracketleft)));
+        assert!(is_quit(menu.get_action(ctrl(), Keysym::g)));
+        assert!(!is_quit(menu.get_action(none, Keysym::q)));
+    }
+
+    #[test]
+    fn custom_quit_keys() {
+        let menu = menu("quit_keys: [q]\nmenu: [{ key: a, desc: A, cmd: a }]");
+        let none = ModifierState::default();
+        assert!(is_quit(menu.get_action(none, Keysym::q)));
+        assert!(!is_quit(menu.get_action(none, Keysym::Escape)));
+        assert!(!is_quit(menu.get_action(ctrl(), Keysym::g)));
+    }
+
+    #[test]
+    fn entries_shadow_quit_keys() {
+        let menu = menu("quit_keys: [q]\nmenu: [{ key: q, desc: Q, cmd: q }]");
+        let action = menu.get_action(ModifierState::default(), Keysym::q);
+        assert!(matches!(action, Some(Action::Exec { cmd, .. }) if cmd == ["q"]));
+    }
+
+    #[test]
+    fn fixed_size_ignores_contents() {
+        let mut menu = menu("menu: [{ key: a, desc: A, cmd: a }]");
+        let natural = (menu.width(), menu.height());
+        menu.set_fixed_size((Some(300.0), None));
+        assert_eq!((menu.width(), menu.height()), (300.0, natural.1));
+        menu.set_fixed_size((None, Some(400.0)));
+        assert_eq!(menu.height(), 400.0);
+    }
+
+    #[test]
+    fn uneven_insets() {
+        let even = menu("border_width: 2\nmenu: [{ key: a, desc: A, cmd: a }]");
+        let uneven = menu(
+            "border_width: 2\nborder_widths: { left: 6, bottom: 10 }\nmenu: [{ key: a, desc: A, cmd: a }]",
+        );
+        assert_eq!(uneven.width(), even.width() + 4.0);
+        assert_eq!(uneven.height(), even.height() + 8.0);
+    }
+
+    #[test]
+    fn initial_keys_again_from_top() {
+        let mut menu = menu(
+            "menu:
+  - key: p
+    desc: Player
+    submenu: [{ key: p, desc: Pause, cmd: playerctl pause }]",
+        );
+        assert!(menu.navigate_to_key_sequence("p").unwrap().is_none());
+        // Showing the menu again in daemon mode starts from the top-level menu.
+        menu.set_page(0);
+        assert!(menu.navigate_to_key_sequence("p").unwrap().is_none());
+        assert_eq!(menu.cur_page, 1);
+    }
+
+    #[test]
+    fn wildcard_catches_unused_keys() {
+        let menu = menu(
+            "menu:
+  - { key: '*', desc: Any, cmd: any }
+  - { key: a, desc: A, cmd: a }",
+        );
+        let none = ModifierState::default();
+        let cmd = |sym| match menu.get_action(none, sym) {
+            Some(Action::Exec { cmd, .. }) => cmd,
+            _ => panic!("not a command"),
+        };
+        // Specific keys win even when bound after the wildcard.
+        assert_eq!(cmd(Keysym::a), ["a"]);
+        assert_eq!(cmd(Keysym::b), ["any"]);
+        assert_eq!(cmd(Keysym::asterisk), ["any"]);
+        // Quit keys come before the wildcard, modifier keys are never caught.
+        assert!(is_quit(menu.get_action(none, Keysym::Escape)));
+        assert!(menu.get_action(none, Keysym::Shift_L).is_none());
+    }
+
+    #[test]
+    fn layout_without_surface() {
+        let menu = menu(
+            "padding: 10\nborder_width: 2\nmenu:\n  - { key: a, desc: A, cmd: a }\n  - { key: b, desc: B, cmd: b }",
+        );
+        let inset = 12.0;
+        let rects = menu.entry_rects();
+        assert_eq!(rects.len(), 2);
+        let (item, first) = &rects[0];
+        assert_eq!(*item, 0);
+        assert_eq!((first.x, first.y), (inset, inset));
+        assert!(first.width > 0.0 && first.height > 0.0);
+        assert_eq!(menu.width(), first.width + 2.0 * inset);
+        assert_eq!(menu.height(), 2.0 * first.height + 2.0 * inset);
+        assert_eq!(rects[1].1.y, first.y + first.height);
+        assert_eq!(
+            menu.item_at(inset + 1.0, first.y + first.height + 1.0),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn row_spacing() {
+        let config = "menu:\n  - { key: a, desc: A, cmd: a }\n  - { key: b, desc: B, cmd: b }";
+        let plain = menu(config);
+        let spaced = menu(&format!("row_spacing: 5\n{config}"));
+        // Only between rows, not after the last one.
+        assert_eq!(spaced.height(), plain.height() + 5.0);
+        let rects = spaced.entry_rects();
+        assert_eq!(rects[1].1.y, rects[0].1.y + rects[0].1.height + 5.0);
+    }
+
+    #[test]
+    fn key_desc_gap() {
+        let config = "menu: [{ key: a, desc: A, cmd: a }]";
+        let plain = menu(config);
+        let gap = menu(&format!("key_desc_gap: 8\n{config}"));
+        assert_eq!(gap.width(), plain.width() + 8.0);
+        assert_eq!(gap.key_desc_width(), plain.key_desc_width() + 8.0);
+    }
+
+    #[test]
+    fn parent_pages() {
+        let mut menu = menu(
+            "menu:
+  - key: p
+    desc: Player
+    submenu: [{ key: s, desc: Sub, submenu: [{ key: n, desc: Next, cmd: n }] }]",
+        );
+        assert_eq!(menu.page(), 0);
+        assert_eq!(menu.parent_page(0), None);
+        let Some(Action::Submenu(player)) = menu.get_action(ModifierState::default(), Keysym::p)
+        else {
+            panic!("not a submenu");
+        };
+        assert_eq!(menu.parent_page(player), Some(0));
+        menu.set_page(player);
+        let Some(Action::Submenu(sub)) = menu.get_action(ModifierState::default(), Keysym::s)
+        else {
+            panic!("not a submenu");
+        };
+        assert_eq!(menu.parent_page(sub), Some(player));
+    }
+
+    #[test]
+    fn text_align_offsets() {
+        use config::TextAlign;
+        assert_eq!(align_offset(TextAlign::Left, 10.0), 0.0);
+        assert_eq!(align_offset(TextAlign::Center, 10.0), 5.0);
+        assert_eq!(align_offset(TextAlign::Right, 10.0), 10.0);
+        // Descriptions cut by `max_width` may be wider than the space.
+        assert_eq!(align_offset(TextAlign::Right, -3.0), 0.0);
+    }
+
+    #[test]
+    fn empty_submenu_has_no_entries() {
+        let mut menu = menu(
+            "menu:
+  - { key: e, desc: Empty, submenu: [{ separator: Nothing here }] }
+  - { key: f, desc: Full, submenu: [{ key: a, desc: A, cmd: a }] }",
+        );
+        assert!(menu.has_entries());
+        assert!(menu.navigate_to_key_sequence("e").unwrap().is_none());
+        assert!(!menu.has_entries());
+        menu.set_page(0);
+        assert!(menu.navigate_to_key_sequence("f").unwrap().is_none());
+        assert!(menu.has_entries());
+    }
+
+    #[test]
+    fn min_size_clamps() {
+        let config = "padding: 10\nborder_width: 0\nmenu: [{ key: a, desc: A, cmd: a }]";
+        let natural = menu(config);
+        let (width, height) = (natural.width(), natural.height());
+
+        // Below the range the minimum wins, within it the contents do.
+        let under = menu(&format!(
+            "min_width: {}\nmin_height: {}\n{config}",
+            width + 100.0,
+            height + 50.0
+        ));
+        assert_eq!(
+            (under.width(), under.height()),
+            (width + 100.0, height + 50.0)
+        );
+        let within = menu(&format!(
+            "min_width: 1\nmin_height: 1\nmax_width: 1000\nmax_height: 1000\n{config}"
+        ));
+        assert_eq!((within.width(), within.height()), (width, height));
+
+        // Above it the maximum wins and the rows scroll.
+        let tall = menu(&format!(
+            "padding: 10\nborder_width: 0\nrows_per_column: 10\nmin_height: 1\nmax_height: {}\nmenu: [{}]",
+            height + 10.0,
+            (b'a'..=b'j')
+                .map(|c| format!("{{ key: {0}, desc: {0}, cmd: {0} }}", c as char))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        assert_eq!(tall.height(), height + 10.0);
+        assert!(tall.rows_per_view.is_some());
+    }
+
+    #[test]
+    fn min_size_centers_contents() {
+        let config = "padding: 10\nborder_width: 0\nmenu: [{ key: a, desc: A, cmd: a }]";
+        let natural = menu(config);
+        let centered = menu(&format!(
+            "min_width: {}\nmin_height: {}\nvertical_align: center\n{config}",
+            natural.width() + 100.0,
+            natural.height() + 40.0
+        ));
+        assert_eq!(centered.content_offset, (50.0, 20.0));
+        let rect = centered.entry_rects()[0].1;
+        assert_eq!((rect.x, rect.y), (60.0, 30.0));
+        assert_eq!(centered.item_at(15.0, 25.0), None);
+        assert_eq!(centered.item_at(61.0, 31.0), Some(0));
+    }
+
+    #[test]
+    fn modifier_style() {
+        let key_width = |style: &str| {
+            // Proportional, so that bold is wider.
+            let menu = menu(&format!(
+                "font: DejaVu Sans 12\nmodifier_style: {style}\nmenu: [{{ key: Ctrl+Alt+x, desc: X, cmd: x }}]"
+            ));
+            menu.pages[0].items[0].key_comp.width
+        };
+        let plain = key_width("plain");
+        assert!(key_width("small") < plain);
+        assert!(key_width("bold") > plain);
+        assert_eq!(key_width("dim"), plain);
+    }
+
+    #[test]
+    fn debug_layout_overlay() {
+        let config = "menu: [{ key: a, desc: A, cmd: a }]";
+        let plain = rendered_pixels(config);
+        crate::DEBUG_LAYOUT.set(true);
+        let outlined = rendered_pixels(config);
+        crate::DEBUG_LAYOUT.set(false);
+        assert_ne!(outlined, plain);
+        assert_eq!(rendered_pixels(config), plain);
+    }
+
+    #[test]
+    fn max_entries() {
+        let entries = "menu:
+  - { separator: Apps }
+  - { key: a, desc: Alpha, cmd: a }
+  - { separator: true }
+  - { key: b, desc: Beta, cmd: b }
+  - { key: c, desc: Gamma, cmd: c }
+  - { key: d, desc: Delta, cmd: d }";
+        // Separators do not count as entries.
+        let capped = menu(&format!("max_entries: 2\n{entries}"));
+        assert_eq!(visible(&capped), ["Apps", "Alpha", "", "Beta"]);
+        assert_eq!(capped.limited_items().1, 2);
+        assert_eq!(capped.overflow.as_ref().unwrap().layout.text(), "… 2 more");
+        assert_eq!(capped.footer().count(), 1);
+
+        let uncut = menu(&format!("max_entries: 4\n{entries}"));
+        assert_eq!(visible(&uncut).len(), 6);
+        assert!(uncut.overflow.is_none());
+    }
+
+    #[test]
+    fn vertical_align() {
+        let first_rect = |align: &str| {
+            let menu = menu(&format!(
+                "padding: 10\nborder_width: 0\nmin_height: 200\nvertical_align: {align}\nmenu: [{{ key: a, desc: A, cmd: a }}]"
+            ));
+            assert_eq!(menu.height(), 200.0);
+            menu.entry_rects()[0].1
+        };
+        let top = first_rect("top");
+        assert_eq!(top.y, 10.0);
+        assert_eq!(first_rect("center").y, 10.0 + (180.0 - top.height) * 0.5);
+        assert_eq!(first_rect("bottom").y, 190.0 - top.height);
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use smithay_client_toolkit::output::{Mode, OutputHandler, OutputState};
+use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
+use smithay_client_toolkit::{delegate_output, delegate_registry};
+use wayland_client::globals::registry_queue_init;
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::{Connection, QueueHandle};
+
+/// Just enough state to learn about the outputs.
+struct Outputs {
+    registry_state: RegistryState,
+    output: OutputState,
+}
+
+/// Print the name, description, current mode and scale of each output, for `--list-outputs`.
+pub fn list() -> Result<()> {
+    let conn = Connection::connect_to_env().context("Failed to connect to the compositor")?;
+    let (globals, mut event_queue) = registry_queue_init(&conn)?;
+    let qh = event_queue.handle();
+    let mut state = Outputs {
+        registry_state: RegistryState::new(&globals),
+        output: OutputState::new(&globals, &qh),
+    };
+    // The first roundtrip binds the outputs, the second one receives their properties.
+    event_queue.roundtrip(&mut state)?;
+    event_queue.roundtrip(&mut state)?;
+
+    for output in state.output.outputs() {
+        let Some(info) = state.output.info(&output) else {
+            continue;
+        };
+        println!(
+            "{}",
+            output_line(
+                info.name.as_deref(),
+                &info.modes,
+                info.scale_factor,
+                info.description.as_deref(),
+            )
+        );
+    }
+    Ok(())
+}
+
+/// One tab-separated line of the `--list-outputs` listing.
+fn output_line(
+    name: Option<&str>,
+    modes: &[Mode],
+    scale: i32,
+    description: Option<&str>,
+) -> String {
+    let name = name.unwrap_or("(unnamed)");
+    let mode = modes
+        .iter()
+        .find(|mode| mode.current)
+        .map(|mode| format!("{}x{}", mode.dimensions.0, mode.dimensions.1))
+        .unwrap_or_else(|| "unknown mode".to_owned());
+    let mut line = format!("{name}\t{mode}\tscale {scale}");
+    if let Some(description) = description {
+        line.push('\t');
+        line.push_str(description);
+    }
+    line
+}
+
+impl OutputHandler for Outputs {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    }
+}
+
+impl ProvidesRegistryState for Outputs {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+
+    fn runtime_add_global(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _name: u32,
+        _interface: &str,
+        _version: u32,
+    ) {
+    }
+
+    fn runtime_remove_global(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _name: u32,
+        _interface: &str,
+    ) {
+    }
+}
+
+delegate_output!(Outputs);
+delegate_registry!(Outputs);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(dimensions: (i32, i32), current: bool) -> Mode {
+        Mode {
+            dimensions,
+            refresh_rate: 60_000,
+            current,
+            preferred: false,
+        }
+    }
+
+    #[test]
+    fn output_lines() {
+        let modes = [mode((1280, 720), false), mode((1920, 1080), true)];
+        assert_eq!(
+            output_line(Some("DP-1"), &modes, 2, Some("Dell U2720Q")),
+            "DP-1\t1920x1080\tscale 2\tDell U2720Q"
+        );
+        assert_eq!(
+            output_line(None, &modes[..1], 1, None),
+            "(unnamed)\tunknown mode\tscale 1"
+        );
+    }
+}
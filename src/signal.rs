@@ -0,0 +1,82 @@
+use std::io;
+use std::mem::{MaybeUninit, size_of};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+
+/// Receives a signal through a file descriptor, so that it can be handled by the event loop.
+///
+/// The signal is blocked for the calling thread and the threads it spawns afterwards, otherwise it
+/// would still be delivered the usual way. Processes spawned with `Command` start with no signals
+/// blocked, so they are not affected.
+pub struct SignalFd {
+    fd: OwnedFd,
+}
+
+impl SignalFd {
+    pub fn new(signal: libc::c_int) -> io::Result<Self> {
+        let mask = signal_set(signal);
+        // Safety: the mask is initialized and the returned descriptor is owned by no one else.
+        unsafe {
+            if libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC);
+            if fd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self {
+                fd: OwnedFd::from_raw_fd(fd),
+            })
+        }
+    }
+
+    /// Consume all pending signals. Returns `true` if there were any.
+    pub fn received(&mut self) -> io::Result<bool> {
+        let mut info = MaybeUninit::<libc::signalfd_siginfo>::uninit();
+        let mut received = false;
+        loop {
+            let size = size_of::<libc::signalfd_siginfo>();
+            // Safety: `info` has room for `size` bytes.
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), info.as_mut_ptr().cast(), size) };
+            if n == -1 {
+                let err = io::Error::last_os_error();
+                return match err.kind() {
+                    io::ErrorKind::WouldBlock => Ok(received),
+                    _ => Err(err),
+                };
+            }
+            received = true;
+        }
+    }
+}
+
+impl AsFd for SignalFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+fn signal_set(signal: libc::c_int) -> libc::sigset_t {
+    let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+    // Safety: sigemptyset() initializes the set.
+    unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), signal);
+        set.assume_init()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The signal is only blocked in the thread of the test, so it does not affect the others.
+    #[test]
+    fn signal_fd() {
+        let mut signal_fd = SignalFd::new(libc::SIGUSR1).unwrap();
+        assert!(!signal_fd.received().unwrap());
+        // Safety: the signal is blocked in this thread, so it is only queued.
+        unsafe { libc::pthread_kill(libc::pthread_self(), libc::SIGUSR1) };
+        assert!(signal_fd.received().unwrap());
+        assert!(!signal_fd.received().unwrap());
+    }
+}
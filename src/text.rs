@@ -1,8 +1,26 @@
-use crate::{DEBUG_LAYOUT, color::Color};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::{color::Color, debug_layout};
 use anyhow::Result;
 use pango::FontDescription;
 use pangocairo::{cairo, pango};
 
+thread_local! {
+    /// Pixel sizes of plain texts, by text and font, so that texts shown again, such as the
+    /// entries of a reloaded menu, are not measured twice.
+    static SIZE_CACHE: RefCell<HashMap<(String, String), (i32, i32)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Number of sizes kept before the cache is emptied.
+const SIZE_CACHE_CAPACITY: usize = 4096;
+
+/// Forget the measured sizes, for when the available fonts may have changed.
+pub fn clear_size_cache() {
+    SIZE_CACHE.with_borrow_mut(|cache| cache.clear());
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct RenderOptions {
     pub x: f64,
@@ -20,12 +38,103 @@ pub struct ComputedText {
 
 impl ComputedText {
     pub fn new(text: impl AsRef<str>, context: &pango::Context, font: &FontDescription) -> Self {
+        let text = text.as_ref();
         let layout = pango::Layout::new(context);
         layout.set_font_description(Some(font));
-        layout.set_markup(text.as_ref());
+        layout.set_text(text);
+        let size = SIZE_CACHE.with_borrow_mut(|cache| {
+            let key = (text.to_owned(), font.to_string());
+            if let Some(&size) = cache.get(&key) {
+                return size;
+            }
+            if cache.len() >= SIZE_CACHE_CAPACITY {
+                cache.clear();
+            }
+            *cache.entry(key).or_insert_with(|| layout.pixel_size())
+        });
+        Self::with_size(layout, size)
+    }
+
+    /// Like `new`, but `text` is interpreted as Pango markup. Invalid markup is shown as is.
+    pub fn new_markup(
+        text: impl AsRef<str>,
+        context: &pango::Context,
+        font: &FontDescription,
+    ) -> Self {
+        let text = text.as_ref();
+        let layout = pango::Layout::new(context);
+        layout.set_font_description(Some(font));
+        match pango::parse_markup(text, '\0') {
+            Ok(_) => layout.set_markup(text),
+            Err(err) => {
+                eprintln!("Warning: invalid markup in '{text}': {err}");
+                layout.set_text(text);
+            }
+        }
+        Self::from_layout(layout)
+    }
+
+    /// A copy of this text with different contents and the same font.
+    pub fn with_text(&self, text: impl AsRef<str>) -> Self {
+        let layout = self.layout.copy();
+        layout.set_text(text.as_ref());
+        Self::from_layout(layout)
+    }
 
-        let (width, height) = layout.pixel_size();
+    /// Cut the text with an ellipsis if it is wider than `max_width`, or show it whole if `None`.
+    pub fn set_max_width(&mut self, max_width: Option<f64>) {
+        match max_width {
+            Some(max_width) => {
+                self.layout.set_width(pango::units_from_double(max_width));
+                self.layout.set_ellipsize(pango::EllipsizeMode::End);
+            }
+            None => {
+                self.layout.set_width(-1);
+                self.layout.set_ellipsize(pango::EllipsizeMode::None);
+            }
+        }
+        let (width, height) = self.layout.pixel_size();
+        self.width = width as f64;
+        self.height = height as f64;
+    }
 
+    /// Style parts of the text with `attrs`, updating its size.
+    pub fn set_attributes(&mut self, attrs: &pango::AttrList) {
+        self.layout.set_attributes(Some(attrs));
+        let (width, height) = self.layout.pixel_size();
+        self.width = width as f64;
+        self.height = height as f64;
+    }
+
+    /// Underline the characters at the byte `indices` of the text, placed as by `render`.
+    pub fn underline(
+        &self,
+        context: &cairo::Context,
+        options: RenderOptions,
+        indices: &[usize],
+    ) -> Result<()> {
+        context.save()?;
+        context.translate(options.x, options.y + (options.height - self.height) * 0.5);
+        options.fg_color.apply(context);
+        let baseline = pango::units_to_double(self.layout.baseline());
+        for &index in indices {
+            let pos = self.layout.index_to_pos(index as i32);
+            let x = pango::units_to_double(pos.x());
+            let width = pango::units_to_double(pos.width());
+            // The width is negative for right-to-left text.
+            context.rectangle(x.min(x + width), baseline + 1.0, width.abs(), 1.0);
+        }
+        context.fill()?;
+        context.restore()?;
+        Ok(())
+    }
+
+    fn from_layout(layout: pango::Layout) -> Self {
+        let size = layout.pixel_size();
+        Self::with_size(layout, size)
+    }
+
+    fn with_size(layout: pango::Layout, (width, height): (i32, i32)) -> Self {
         ComputedText {
             layout,
             width: width as f64,
@@ -42,7 +151,7 @@ impl ComputedText {
         options.fg_color.apply(context);
         pangocairo::functions::show_layout(context, &self.layout);
 
-        if *DEBUG_LAYOUT {
+        if debug_layout() {
             Color::from_rgba(255, 0, 0, 255).apply(context);
             context.rectangle(0.0, 0.0, self.width, self.height);
             context.set_line_width(1.0);
@@ -54,3 +163,63 @@ impl ComputedText {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> pango::Context {
+        let context = pango::Context::new();
+        context.set_font_map(Some(&pangocairo::FontMap::new()));
+        context
+    }
+
+    #[test]
+    fn markup() {
+        let context = context();
+        let font = FontDescription::from_string("sans 10");
+        let bold = ComputedText::new_markup("<b>bold</b>", &context, &font);
+        let literal = ComputedText::new("<b>bold</b>", &context, &font);
+        assert_eq!(bold.layout.text(), "bold");
+        assert_ne!(bold.width, literal.width);
+    }
+
+    #[test]
+    fn invalid_markup_is_literal() {
+        let context = context();
+        let font = FontDescription::from_string("sans 10");
+        let invalid = ComputedText::new_markup("<b>bold", &context, &font);
+        let literal = ComputedText::new("<b>bold", &context, &font);
+        assert_eq!(invalid.layout.text(), "<b>bold");
+        assert_eq!(invalid.width, literal.width);
+    }
+
+    #[test]
+    fn sizes_are_cached() {
+        let context = context();
+        let font = FontDescription::from_string("sans 10");
+        let key = ("cached".to_owned(), font.to_string());
+        clear_size_cache();
+        let text = ComputedText::new("cached", &context, &font);
+        let cached = SIZE_CACHE.with_borrow(|cache| cache.get(&key).copied());
+        assert_eq!(cached, Some((text.width as i32, text.height as i32)));
+
+        let again = ComputedText::new("cached", &context, &font);
+        assert_eq!((again.width, again.height), (text.width, text.height));
+
+        clear_size_cache();
+        assert!(SIZE_CACHE.with_borrow(|cache| cache.is_empty()));
+    }
+
+    #[test]
+    fn size_cache_is_bounded() {
+        let context = context();
+        let font = FontDescription::from_string("sans 10");
+        clear_size_cache();
+        for i in 0..=SIZE_CACHE_CAPACITY {
+            ComputedText::new(i.to_string(), &context, &font);
+        }
+        // The cache was emptied when full, then got the last text.
+        assert_eq!(SIZE_CACHE.with_borrow(|cache| cache.len()), 1);
+    }
+}
@@ -0,0 +1,93 @@
+use std::ffi::OsString;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::Path;
+
+use inotify::{Inotify, WatchMask};
+
+/// Watches a single file for modifications.
+///
+/// The parent directory is watched instead of the file itself, because most editors save files by
+/// writing a new file and renaming it over the old one, which would silently drop a watch on the
+/// original inode.
+pub struct FileWatcher {
+    inotify: Inotify,
+    file_name: OsString,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| io::Error::other("path has no parent"))?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::other("path has no file name"))?
+            .to_owned();
+
+        let inotify = Inotify::init()?;
+        inotify.watches().add(
+            dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+        )?;
+
+        Ok(Self { inotify, file_name })
+    }
+
+    /// Consume all pending events. Returns `true` if any of them concern the watched file.
+    pub fn changed(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 4096];
+        let mut changed = false;
+        loop {
+            match self.inotify.read_events(&mut buf) {
+                Ok(events) => {
+                    for event in events {
+                        changed |= event.name == Some(self.file_name.as_os_str());
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(changed),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl AsFd for FileWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inotify.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn reports_only_the_watched_file() {
+        let dir = env::temp_dir().join(format!("wlr-which-key-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        fs::write(&path, "menu: []").unwrap();
+
+        let mut watcher = FileWatcher::new(&path).unwrap();
+        assert!(!watcher.changed().unwrap());
+
+        fs::write(dir.join("other.yaml"), "menu: []").unwrap();
+        assert!(!watcher.changed().unwrap());
+
+        fs::write(&path, "menu: [{ key: a, desc: A, cmd: a }]").unwrap();
+        assert!(watcher.changed().unwrap());
+        assert!(!watcher.changed().unwrap());
+
+        // Editors that save by renaming a temporary file over the original.
+        let tmp = dir.join("config.yaml.tmp");
+        fs::write(&tmp, "menu: []").unwrap();
+        watcher.changed().unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        assert!(watcher.changed().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}